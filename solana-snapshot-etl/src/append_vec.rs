@@ -16,14 +16,16 @@
 // Source: solana/runtime/src/append_vec.rs
 
 use {
+    crate::CorruptionReason,
     log::*,
     memmap2::{Mmap, MmapMut},
     serde::{Deserialize, Serialize},
     solana_sdk::{
         account::{Account, AccountSharedData, ReadableAccount},
-        clock::Epoch,
+        clock::{Epoch, Slot},
         hash::Hash,
         pubkey::Pubkey,
+        system_instruction::MAX_PERMITTED_DATA_LENGTH,
     },
     std::{
         convert::TryFrom,
@@ -50,27 +52,31 @@ pub type StoredMetaWriteVersion = u64;
 /// Meta contains enough context to recover the index from storage itself
 /// This struct will be backed by mmaped and snapshotted data files.
 /// So the data layout must be stable and consistent across the entire cluster!
-#[derive(Clone, PartialEq, Eq, Debug)]
+/// Fields are declared in their on-disk order, so `repr(C)` pins the layout.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+#[repr(C)]
 pub struct StoredMeta {
     /// global write version
     pub write_version: StoredMetaWriteVersion,
+    pub data_len: u64,
     /// key for the account
     pub pubkey: Pubkey,
-    pub data_len: u64,
 }
 
 /// This struct will be backed by mmaped and snapshotted data files.
 /// So the data layout must be stable and consistent across the entire cluster!
+/// Fields are declared in their on-disk order, so `repr(C)` pins the layout.
 #[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+#[repr(C)]
 pub struct AccountMeta {
     /// lamports in the account
     pub lamports: u64,
+    /// the epoch at which this account will next owe rent
+    pub rent_epoch: Epoch,
     /// the program that owns this account. If executable, the program that loads this account.
     pub owner: Pubkey,
     /// this account's data contains a loaded program (and is now read-only)
     pub executable: bool,
-    /// the epoch at which this account will next owe rent
-    pub rent_epoch: Epoch,
 }
 
 impl<'a, T: ReadableAccount> From<&'a T> for AccountMeta {
@@ -119,6 +125,24 @@ impl<'a> StoredAccountMeta<'a> {
     }
 }
 
+impl<'a> ReadableAccount for StoredAccountMeta<'a> {
+    fn lamports(&self) -> u64 {
+        self.account_meta.lamports
+    }
+    fn data(&self) -> &[u8] {
+        self.data
+    }
+    fn owner(&self) -> &Pubkey {
+        &self.account_meta.owner
+    }
+    fn executable(&self) -> bool {
+        self.account_meta.executable
+    }
+    fn rent_epoch(&self) -> Epoch {
+        self.account_meta.rent_epoch
+    }
+}
+
 /// A thread-safe, file-backed block of memory used to store `Account` instances. Append operations
 /// are serialized such that only one thread updates the internal `append_lock` at a time. No
 /// restrictions are placed on reading. That is, one may read items from one thread while another
@@ -132,6 +156,12 @@ pub struct AppendVec {
 
     /// The number of bytes available for storing items.
     file_size: u64,
+
+    /// The slot this AppendVec was written in.
+    slot: Slot,
+
+    /// The ID of this AppendVec within its slot.
+    id: u64,
 }
 
 impl AppendVec {
@@ -176,7 +206,20 @@ impl AppendVec {
         self.file_size
     }
 
-    pub fn new_from_file<P: AsRef<Path>>(path: P, current_len: usize) -> io::Result<Self> {
+    pub fn slot(&self) -> Slot {
+        self.slot
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn new_from_file<P: AsRef<Path>>(
+        path: P,
+        current_len: usize,
+        slot: Slot,
+        id: u64,
+    ) -> io::Result<Self> {
         let data = OpenOptions::new()
             .read(true)
             .write(false)
@@ -199,18 +242,27 @@ impl AppendVec {
             map,
             current_len,
             file_size,
+            slot,
+            id,
         };
 
         Ok(new)
     }
 
-    pub fn new_from_reader<R: Read>(reader: &mut R, current_len: usize) -> io::Result<Self> {
+    pub fn new_from_reader<R: Read>(
+        reader: &mut R,
+        current_len: usize,
+        slot: Slot,
+        id: u64,
+    ) -> io::Result<Self> {
         let mut map = MmapMut::map_anon(current_len)?;
         io::copy(&mut reader.take(current_len as u64), &mut map.as_mut())?;
         Ok(AppendVec {
             map: map.make_read_only()?,
             current_len,
             file_size: current_len as u64,
+            slot,
+            id,
         })
     }
 
@@ -245,6 +297,52 @@ impl AppendVec {
         Some((unsafe { &*ptr }, next))
     }
 
+    /// Like `get_account`, but tells the end of the AppendVec apart from a corrupt record.
+    pub fn try_get_account<'a>(
+        &'a self,
+        offset: usize,
+    ) -> crate::Result<Option<(StoredAccountMeta<'a>, usize)>> {
+        if offset >= self.len() {
+            return Ok(None);
+        }
+        let corrupt = |reason| crate::SnapshotError::CorruptAppendVec {
+            slot: self.slot,
+            id: self.id,
+            offset,
+            reason,
+        };
+        if offset % ALIGN_BOUNDARY_OFFSET != 0 {
+            return Err(corrupt(CorruptionReason::Misaligned));
+        }
+        let (meta, next): (&'a StoredMeta, _) = self
+            .get_type(offset)
+            .ok_or_else(|| corrupt(CorruptionReason::MetaOutOfBounds))?;
+        let (account_meta, next): (&'a AccountMeta, _) = self
+            .get_type(next)
+            .ok_or_else(|| corrupt(CorruptionReason::MetaOutOfBounds))?;
+        let (hash, next): (&'a Hash, _) = self
+            .get_type(next)
+            .ok_or_else(|| corrupt(CorruptionReason::HashOutOfBounds))?;
+        if meta.data_len > MAX_PERMITTED_DATA_LENGTH {
+            return Err(corrupt(CorruptionReason::DataLenOverflow));
+        }
+        let (data, next) = self
+            .get_slice(next, meta.data_len as usize)
+            .ok_or_else(|| corrupt(CorruptionReason::DataLenOverflow))?;
+        let stored_size = next - offset;
+        Ok(Some((
+            StoredAccountMeta {
+                meta,
+                account_meta,
+                data,
+                offset,
+                stored_size,
+                hash,
+            },
+            next,
+        )))
+    }
+
     /// Return account metadata for the account at `offset` if its data doesn't overrun
     /// the internal buffer. Otherwise return None. Also return the offset of the first byte
     /// after the requested data that falls on a 64-byte boundary.
@@ -267,3 +365,74 @@ impl AppendVec {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{append_vec_bytes, append_vec_from_bytes, pubkey, TestAccount};
+    use crate::SnapshotError;
+
+    fn corruption(append_vec: &AppendVec, offset: usize) -> (Slot, u64, usize, CorruptionReason) {
+        match append_vec.try_get_account(offset) {
+            Err(SnapshotError::CorruptAppendVec {
+                slot,
+                id,
+                offset,
+                reason,
+            }) => (slot, id, offset, reason),
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+    }
+
+    /// Two accounts, and the offset of the second one.
+    fn two_accounts() -> (Vec<u8>, usize) {
+        let bytes = append_vec_bytes(
+            4,
+            &[
+                TestAccount::new(pubkey(1), 1, 10, &[1; 5]),
+                TestAccount::new(pubkey(2), 2, 20, &[2; 3]),
+            ],
+        );
+        (bytes, 144)
+    }
+
+    #[test]
+    fn reports_truncated_records() {
+        let (bytes, second) = two_accounts();
+        for (len, reason) in [
+            (second + 50, CorruptionReason::MetaOutOfBounds),
+            (second + 120, CorruptionReason::HashOutOfBounds),
+            (second + 137, CorruptionReason::DataLenOverflow),
+        ] {
+            let append_vec = append_vec_from_bytes(4, 1, &bytes[..len]);
+            let (_, next) = append_vec.try_get_account(0).unwrap().unwrap();
+            assert_eq!(next, second);
+            assert_eq!(corruption(&append_vec, next), (4, 1, second, reason));
+            assert!(append_vec.get_account(next).is_none());
+        }
+    }
+
+    #[test]
+    fn reports_misaligned_offsets() {
+        let (bytes, _) = two_accounts();
+        let append_vec = append_vec_from_bytes(4, 1, &bytes);
+        assert_eq!(
+            corruption(&append_vec, 4),
+            (4, 1, 4, CorruptionReason::Misaligned)
+        );
+        assert!(append_vec.try_get_account(bytes.len()).unwrap().is_none());
+    }
+
+    #[test]
+    fn reports_oversized_data_len() {
+        let (mut bytes, second) = two_accounts();
+        for data_len in [MAX_PERMITTED_DATA_LENGTH + 1, u64::MAX] {
+            bytes[second + 8..second + 16].copy_from_slice(&data_len.to_le_bytes());
+            let append_vec = append_vec_from_bytes(4, 1, &bytes);
+            assert_eq!(
+                corruption(&append_vec, second),
+                (4, 1, second, CorruptionReason::DataLenOverflow)
+            );
+        }
+    }
+}
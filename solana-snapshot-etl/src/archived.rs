@@ -1,24 +1,38 @@
+use crate::decompress::ArchiveDecoder;
+use crate::filter::AccountFilter;
+use crate::manifest::parse_snapshot_version;
+use crate::status_cache::{read_status_cache, SlotStatuses};
+use crate::stream::AppendVecReader;
+use crate::unpack::{SnapshotUnpacker, UnpackStats};
 use crate::{
-    deserialize_from, parse_append_vec_name, AccountsDbFields, AppendVec, AppendVecIterator,
-    DeserializableVersionedBank, Result, SerializableAccountStorageEntry, SnapshotError,
-    SnapshotExtractor,
+    parse_append_vec_name, AppendVec, AppendVecIterator, CorruptionPolicy, FilteredAccountCallback,
+    ReadProgressTracking, Result, SnapshotError, SnapshotExtractor, SnapshotManifest,
+    SNAPSHOTS_DIR, VERSION_FILE,
 };
-use log::info;
+use log::{info, warn};
+use solana_runtime::snapshot_utils::{SnapshotVersion, SNAPSHOT_STATUS_CACHE_FILENAME};
+use std::cell::RefCell;
+use std::collections::{HashSet, VecDeque};
 use std::fs::File;
 use std::io::{BufReader, Read};
-use std::path::{Component, Path};
+use std::path::{Component, Path, PathBuf};
 use std::pin::Pin;
-use std::time::Instant;
 use tar::{Archive, Entries, Entry};
+use tempfile::TempDir;
 
-/// Extracts account data from a .tar.zst stream.
+/// Extracts account data from a snapshot archive stream.
+///
+/// Supports zstd, gzip, bzip2, lz4 compressed and uncompressed tar streams.
 pub struct ArchiveSnapshotExtractor<Source>
 where
     Source: Read + Unpin + 'static,
 {
-    accounts_db_fields: AccountsDbFields<SerializableAccountStorageEntry>,
-    _archive: Pin<Box<Archive<zstd::Decoder<'static, BufReader<Source>>>>>,
-    entries: Option<Entries<'static, zstd::Decoder<'static, BufReader<Source>>>>,
+    manifest: SnapshotManifest,
+    _archive: Pin<Box<Archive<ArchiveDecoder<Source>>>>,
+    entries: Option<Entries<'static, ArchiveDecoder<Source>>>,
+    spill: Option<AppendVecSpill>,
+    status_cache: RefCell<Option<Vec<u8>>>,
+    skip: HashSet<(u64, u64)>,
 }
 
 impl<Source> SnapshotExtractor for ArchiveSnapshotExtractor<Source>
@@ -28,6 +42,53 @@ where
     fn iter(&mut self) -> AppendVecIterator<'_> {
         Box::new(self.unboxed_iter())
     }
+
+    fn manifest(&self) -> &SnapshotManifest {
+        &self.manifest
+    }
+
+    /// Parses accounts straight from the archive, one at a time.
+    fn for_each_filtered_account(
+        &mut self,
+        policy: CorruptionPolicy,
+        filter: &AccountFilter,
+        f: &mut FilteredAccountCallback<'_>,
+    ) -> Result<()> {
+        if let Some(spill) = self.spill.take() {
+            for (slot, id, path) in spill {
+                if !self.skip.contains(&(slot, id)) {
+                    let entry = BufReader::new(File::open(&path)?);
+                    self.stream_entry(entry, slot, id, policy, filter, f)?;
+                }
+                std::fs::remove_file(&path)?;
+            }
+        }
+        for entry in self.entries.take().into_iter().flatten() {
+            let mut entry = entry?;
+            let path = entry.path()?;
+            if Self::is_status_cache_file(&path) {
+                self.capture_status_cache(&mut entry)?;
+                continue;
+            }
+            let name = path.file_name().and_then(parse_append_vec_name);
+            if let Some((slot, id)) = name.filter(|name| !self.skip.contains(name)) {
+                self.stream_entry(BufReader::new(entry), slot, id, policy, filter, f)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn status_cache(&self) -> Result<Option<Vec<SlotStatuses>>> {
+        self.status_cache
+            .borrow()
+            .as_deref()
+            .map(read_status_cache)
+            .transpose()
+    }
+
+    fn skip_append_vecs(&mut self, append_vecs: HashSet<(u64, u64)>) {
+        self.skip = append_vecs;
+    }
 }
 
 impl<Source> ArchiveSnapshotExtractor<Source>
@@ -35,7 +96,30 @@ where
     Source: Read + Unpin + 'static,
 {
     pub fn from_reader(source: Source) -> Result<Self> {
-        let tar_stream = zstd::stream::read::Decoder::new(source)?;
+        Self::from_reader_inner(source, None)
+    }
+
+    /// Like `from_reader`, but accepts archives where AppendVecs precede the snapshot manifest.
+    ///
+    /// Such AppendVecs are spilled to a temporary directory under `spill_dir`
+    /// and replayed before the rest of the archive once the manifest is known.
+    pub fn from_reader_spilling(source: Source, spill_dir: &Path) -> Result<Self> {
+        Self::from_reader_inner(source, Some(spill_dir))
+    }
+
+    fn from_reader_inner(source: Source, spill_dir: Option<&Path>) -> Result<Self> {
+        Self::from_decoder(ArchiveDecoder::new(source)?, spill_dir)
+    }
+
+    /// Like `from_reader`, but reads the archive through the given decoder,
+    /// such as one set up with `ArchiveDecoder::pipelined`.
+    ///
+    /// AppendVecs preceding the snapshot manifest are spilled under `spill_dir` if one is given,
+    /// see `from_reader_spilling`.
+    pub fn from_decoder(
+        tar_stream: ArchiveDecoder<Source>,
+        spill_dir: Option<&Path>,
+    ) -> Result<Self> {
         let mut archive = Box::pin(Archive::new(tar_stream));
 
         // This is safe as long as we guarantee that entries never gets accessed past drop.
@@ -44,92 +128,176 @@ where
 
         // Search for snapshot manifest.
         let mut snapshot_file: Option<Entry<_>> = None;
+        let mut spill: Option<AppendVecSpill> = None;
+        let mut version: Option<SnapshotVersion> = None;
+        let mut status_cache: Option<Vec<u8>> = None;
         for entry in entries.by_ref() {
-            let entry = entry?;
+            let mut entry = entry?;
             let path = entry.path()?;
-            if Self::is_snapshot_manifest_file(&path) {
+            if Self::is_status_cache_file(&path) {
+                let mut buf = Vec::new();
+                entry.read_to_end(&mut buf)?;
+                status_cache = Some(buf);
+            } else if path == Path::new(VERSION_FILE) {
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents)?;
+                version = Some(parse_snapshot_version(&contents)?);
+            } else if Self::is_snapshot_manifest_file(&path) {
                 snapshot_file = Some(entry);
                 break;
             } else if Self::is_appendvec_file(&path) {
-                // TODO Support archives where AppendVecs precede snapshot manifests
-                return Err(SnapshotError::UnexpectedAppendVec);
+                let (slot, id) = path
+                    .file_name()
+                    .and_then(parse_append_vec_name)
+                    .ok_or(SnapshotError::UnexpectedAppendVec)?;
+                let spill_dir = spill_dir.ok_or(SnapshotError::UnexpectedAppendVec)?;
+                if spill.is_none() {
+                    spill = Some(AppendVecSpill::new(spill_dir)?);
+                }
+                spill.as_mut().unwrap().push(&mut entry, slot, id)?;
             }
         }
+        if let Some(spill) = &spill {
+            info!(
+                "Spilled {} AppendVecs preceding the snapshot manifest",
+                spill.vecs.len()
+            );
+        }
         let snapshot_file = snapshot_file.ok_or(SnapshotError::NoSnapshotManifest)?;
         //let snapshot_file_len = snapshot_file.size();
         let snapshot_file_path = snapshot_file.path()?.as_ref().to_path_buf();
 
         info!("Opening snapshot manifest: {:?}", &snapshot_file_path);
-        let mut snapshot_file = BufReader::new(snapshot_file);
-
-        let pre_unpack = Instant::now();
-        let versioned_bank: DeserializableVersionedBank = deserialize_from(&mut snapshot_file)?;
-        drop(versioned_bank);
-        let versioned_bank_post_time = Instant::now();
-
-        let accounts_db_fields: AccountsDbFields<SerializableAccountStorageEntry> =
-            deserialize_from(&mut snapshot_file)?;
-        let accounts_db_fields_post_time = Instant::now();
-        drop(snapshot_file);
-
-        info!(
-            "Read bank fields in {:?}",
-            versioned_bank_post_time - pre_unpack
-        );
-        info!(
-            "Read accounts DB fields in {:?}",
-            accounts_db_fields_post_time - versioned_bank_post_time
-        );
+        let version = version.unwrap_or_else(|| {
+            warn!(
+                "No snapshot version file before manifest, assuming {}",
+                SnapshotVersion::default()
+            );
+            SnapshotVersion::default()
+        });
+        let manifest = SnapshotManifest::read_from(BufReader::new(snapshot_file), version)?;
 
         Ok(ArchiveSnapshotExtractor {
             _archive: archive,
-            accounts_db_fields,
+            manifest,
             entries: Some(entries),
+            spill,
+            status_cache: RefCell::new(status_cache),
+            skip: HashSet::new(),
         })
     }
 
     fn unboxed_iter(&mut self) -> impl Iterator<Item = Result<AppendVec>> + '_ {
-        self.entries
-            .take()
+        let spill = self.spill.take();
+        let entries = self.entries.take();
+        let this = &*self;
+        let spilled_iter = spill
             .into_iter()
             .flatten()
-            .filter_map(|entry| {
-                let mut entry = match entry {
-                    Ok(x) => x,
-                    Err(e) => return Some(Err(e.into())),
-                };
-                let path = match entry.path() {
-                    Ok(x) => x,
-                    Err(e) => return Some(Err(e.into())),
-                };
-                let (slot, id) = path.file_name().and_then(parse_append_vec_name)?;
-                Some(self.process_entry(&mut entry, slot, id))
-            })
-    }
-
-    fn process_entry(
-        &self,
-        entry: &mut Entry<'static, zstd::Decoder<'static, BufReader<Source>>>,
-        slot: u64,
-        id: u64,
-    ) -> Result<AppendVec> {
-        let known_vecs = self
-            .accounts_db_fields
-            .0
-            .get(&slot)
-            .map(|v| &v[..])
-            .unwrap_or(&[]);
-        let known_vec = known_vecs.iter().find(|entry| entry.id == (id as usize));
-        let known_vec = match known_vec {
-            None => return Err(SnapshotError::UnexpectedAppendVec),
-            Some(v) => v,
-        };
+            .filter_map(move |(slot, id, path)| {
+                let append_vec = (!this.skip.contains(&(slot, id)))
+                    .then(|| this.process_entry(&mut File::open(&path)?, slot, id));
+                match std::fs::remove_file(&path) {
+                    Ok(()) => append_vec,
+                    Err(e) => Some(Err(e.into())),
+                }
+            });
+        let entries_iter = entries.into_iter().flatten().filter_map(move |entry| {
+            let mut entry = match entry {
+                Ok(x) => x,
+                Err(e) => return Some(Err(e.into())),
+            };
+            let path = match entry.path() {
+                Ok(x) => x,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if Self::is_status_cache_file(&path) {
+                return this.capture_status_cache(&mut entry).err().map(Err);
+            }
+            let (slot, id) = path.file_name().and_then(parse_append_vec_name)?;
+            if this.skip.contains(&(slot, id)) {
+                return None;
+            }
+            Some(this.process_entry(&mut entry, slot, id))
+        });
+        spilled_iter.chain(entries_iter)
+    }
+
+    fn process_entry<R: Read>(&self, entry: &mut R, slot: u64, id: u64) -> Result<AppendVec> {
+        let known_vec = self
+            .manifest
+            .append_vec_entry(slot, id)
+            .ok_or(SnapshotError::UnexpectedAppendVec)?;
         Ok(AppendVec::new_from_reader(
             entry,
             known_vec.accounts_current_len,
+            slot,
+            id,
         )?)
     }
 
+    fn stream_entry<R: Read>(
+        &self,
+        entry: R,
+        slot: u64,
+        id: u64,
+        policy: CorruptionPolicy,
+        filter: &AccountFilter,
+        f: &mut FilteredAccountCallback<'_>,
+    ) -> Result<()> {
+        let known_vec = self
+            .manifest
+            .append_vec_entry(slot, id)
+            .ok_or(SnapshotError::UnexpectedAppendVec)?;
+        let mut reader = AppendVecReader::new(entry, known_vec.accounts_current_len, slot, id);
+        loop {
+            match reader.next_filtered_account(filter) {
+                Ok(Some(account)) => f(slot, id, account)?,
+                Ok(None) => return Ok(()),
+                Err(err) => return policy.check(err),
+            }
+        }
+    }
+
+    /// Streams the rest of the archive into `out_dir` once, in the layout read by `UnpackedSnapshotExtractor`.
+    ///
+    /// See `SnapshotUnpacker` for how AppendVecs are checked and filtered.
+    pub fn unpack(&mut self, out_dir: &Path, filter: AccountFilter) -> Result<UnpackStats> {
+        let mut unpacker = SnapshotUnpacker::create(out_dir, filter)?;
+        if let Some(spill) = self.spill.take() {
+            for (slot, id, path) in spill {
+                let file = File::open(&path)?;
+                let size = file.metadata()?.len();
+                unpacker.write_append_vec(&self.manifest, BufReader::new(file), size, slot, id)?;
+                std::fs::remove_file(&path)?;
+            }
+        }
+        for entry in self.entries.take().into_iter().flatten() {
+            let mut entry = entry?;
+            let path = entry.path()?;
+            if Self::is_status_cache_file(&path) {
+                self.capture_status_cache(&mut entry)?;
+                continue;
+            }
+            if let Some((slot, id)) = path.file_name().and_then(parse_append_vec_name) {
+                let size = entry.size();
+                unpacker.write_append_vec(&self.manifest, BufReader::new(entry), size, slot, id)?;
+            }
+        }
+        unpacker.finish(&self.manifest, self.status_cache.borrow().as_deref())
+    }
+
+    fn capture_status_cache<R: Read>(&self, entry: &mut R) -> Result<()> {
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf)?;
+        *self.status_cache.borrow_mut() = Some(buf);
+        Ok(())
+    }
+
+    fn is_status_cache_file(path: &Path) -> bool {
+        path == Path::new(SNAPSHOTS_DIR).join(SNAPSHOT_STATUS_CACHE_FILENAME)
+    }
+
     fn is_snapshot_manifest_file(path: &Path) -> bool {
         let mut components = path.components();
         if components.next() != Some(Component::Normal("snapshots".as_ref())) {
@@ -172,3 +340,57 @@ impl ArchiveSnapshotExtractor<File> {
         Self::from_reader(File::open(path)?)
     }
 }
+
+impl ArchiveSnapshotExtractor<Box<dyn Read>> {
+    /// Like `open`, but reports progress of reading the compressed archive.
+    pub fn open_with_progress(
+        path: &Path,
+        progress_tracking: Box<dyn ReadProgressTracking>,
+    ) -> Result<Self> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        Self::from_reader(progress_tracking.new_read_progress_tracker(
+            path,
+            Box::new(file),
+            file_len,
+        ))
+    }
+}
+
+/// AppendVecs that were found before the snapshot manifest, buffered on disk.
+///
+/// Iterating yields the spilled files in archive order.
+/// The directory is removed once the spill is dropped.
+struct AppendVecSpill {
+    dir: TempDir,
+    vecs: VecDeque<(u64, u64, PathBuf)>,
+}
+
+impl AppendVecSpill {
+    fn new(spill_dir: &Path) -> Result<Self> {
+        let dir = tempfile::Builder::new()
+            .prefix("appendvec-spill")
+            .tempdir_in(spill_dir)?;
+        info!("Spilling early AppendVecs to {:?}", dir.path());
+        Ok(Self {
+            dir,
+            vecs: VecDeque::new(),
+        })
+    }
+
+    fn push<R: Read>(&mut self, rd: &mut R, slot: u64, id: u64) -> Result<()> {
+        let path = self.dir.path().join(format!("{}.{}", slot, id));
+        let mut file = File::create(&path)?;
+        std::io::copy(rd, &mut file)?;
+        self.vecs.push_back((slot, id, path));
+        Ok(())
+    }
+}
+
+impl Iterator for AppendVecSpill {
+    type Item = (u64, u64, PathBuf);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.vecs.pop_front()
+    }
+}
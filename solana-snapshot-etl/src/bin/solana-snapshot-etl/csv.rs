@@ -1,9 +1,8 @@
 use indicatif::{ProgressBar, ProgressStyle};
 use serde::Serialize;
-use solana_snapshot_etl::append_vec::{AppendVec, StoredAccountMeta};
-use solana_snapshot_etl::append_vec_iter;
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use solana_snapshot_etl::dedup::LatestAccounts;
 use std::io::Stdout;
-use std::rc::Rc;
 
 pub(crate) struct CsvDumper {
     accounts_spinner: ProgressBar,
@@ -38,11 +37,14 @@ impl CsvDumper {
         }
     }
 
-    pub(crate) fn dump_append_vec(&mut self, append_vec: AppendVec) {
-        for account in append_vec_iter(Rc::new(append_vec)) {
-            let account = account.access().unwrap();
-            self.dump_account(account);
+    pub(crate) fn dump_accounts(
+        &mut self,
+        accounts: LatestAccounts,
+    ) -> solana_snapshot_etl::Result<()> {
+        for account in accounts {
+            self.dump_account(account?.access());
         }
+        Ok(())
     }
 
     pub(crate) fn dump_account(&mut self, account: StoredAccountMeta) {
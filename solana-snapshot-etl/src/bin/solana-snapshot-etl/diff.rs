@@ -0,0 +1,219 @@
+use crate::sqlite::TempFileGuard;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use solana_snapshot_etl::diff::{AccountDiff, DiffKind};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+pub(crate) type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub(crate) enum DiffFormat {
+    Csv,
+    Jsonl,
+    Sqlite,
+}
+
+/// Writes account diffs as CSV, JSON lines or into a new SQLite3 DB.
+pub(crate) enum DiffDumper {
+    Csv(csv::Writer<Box<dyn Write>>),
+    Jsonl(BufWriter<Box<dyn Write>>),
+    Sqlite {
+        db: Connection,
+        db_path: PathBuf,
+        db_temp_guard: TempFileGuard,
+    },
+}
+
+#[derive(Default)]
+pub(crate) struct DiffStats {
+    pub(crate) created: u64,
+    pub(crate) deleted: u64,
+    pub(crate) modified: u64,
+}
+
+/// Changed fields of an account, `None` where a version does not exist.
+#[derive(Serialize)]
+struct Record<R> {
+    pubkey: String,
+    change: &'static str,
+    old_lamports: Option<u64>,
+    new_lamports: Option<u64>,
+    lamports_delta: i128,
+    old_owner: Option<String>,
+    new_owner: Option<String>,
+    owner_changed: bool,
+    old_data_len: Option<u64>,
+    new_data_len: Option<u64>,
+    data_ranges: R,
+    old_executable: Option<bool>,
+    new_executable: Option<bool>,
+    executable_changed: bool,
+}
+
+impl<R> Record<R> {
+    fn new(diff: &AccountDiff, data_ranges: R) -> Self {
+        let old = diff.old.as_ref();
+        let new = diff.new.as_ref();
+        Self {
+            pubkey: diff.pubkey().to_string(),
+            change: diff.kind.as_str(),
+            old_lamports: old.map(|a| a.account_meta.lamports),
+            new_lamports: new.map(|a| a.account_meta.lamports),
+            lamports_delta: diff.lamports_delta(),
+            old_owner: old.map(|a| a.account_meta.owner.to_string()),
+            new_owner: new.map(|a| a.account_meta.owner.to_string()),
+            owner_changed: diff.owner_changed(),
+            old_data_len: old.map(|a| a.meta.data_len),
+            new_data_len: new.map(|a| a.meta.data_len),
+            data_ranges,
+            old_executable: old.map(|a| a.account_meta.executable),
+            new_executable: new.map(|a| a.account_meta.executable),
+            executable_changed: diff.executable_changed(),
+        }
+    }
+}
+
+impl DiffDumper {
+    pub(crate) fn new_csv(writer: Box<dyn Write>) -> Self {
+        Self::Csv(csv::Writer::from_writer(writer))
+    }
+
+    pub(crate) fn new_jsonl(writer: Box<dyn Write>) -> Self {
+        Self::Jsonl(BufWriter::new(writer))
+    }
+
+    pub(crate) fn new_sqlite(db_path: PathBuf) -> Result<Self> {
+        let temp_file_name = format!("_{}.tmp", db_path.file_name().unwrap().to_string_lossy());
+        let db_temp_path = db_path.with_file_name(&temp_file_name);
+        let _ = std::fs::remove_file(&db_temp_path);
+        let db_temp_guard = TempFileGuard::new(db_temp_path.clone());
+
+        let db = Connection::open(&db_temp_path)?;
+        db.pragma_update(None, "synchronous", false)?;
+        db.pragma_update(None, "journal_mode", "off")?;
+        db.pragma_update(None, "locking_mode", "exclusive")?;
+        db.execute(
+            "\
+CREATE TABLE account_diff (
+    pubkey BLOB(32) NOT NULL PRIMARY KEY,
+    change TEXT NOT NULL,
+    old_lamports INTEGER(8) NULL,
+    new_lamports INTEGER(8) NULL,
+    lamports_delta INTEGER(8) NOT NULL,
+    old_owner BLOB(32) NULL,
+    new_owner BLOB(32) NULL,
+    old_data_len INTEGER(8) NULL,
+    new_data_len INTEGER(8) NULL,
+    old_executable INTEGER(1) NULL,
+    new_executable INTEGER(1) NULL
+);",
+            [],
+        )?;
+        db.execute(
+            "\
+CREATE TABLE account_diff_data_range (
+    pubkey BLOB(32) NOT NULL,
+    start_offset INTEGER(8) NOT NULL,
+    end_offset INTEGER(8) NOT NULL,
+    PRIMARY KEY (pubkey, start_offset)
+);",
+            [],
+        )?;
+        db.execute_batch("BEGIN")?;
+        Ok(Self::Sqlite {
+            db,
+            db_path,
+            db_temp_guard,
+        })
+    }
+
+    pub(crate) fn dump_diffs<I>(mut self, diffs: I) -> Result<DiffStats>
+    where
+        I: Iterator<Item = solana_snapshot_etl::Result<AccountDiff>>,
+    {
+        let mut stats = DiffStats::default();
+        for diff in diffs {
+            let diff = diff?;
+            match diff.kind {
+                DiffKind::Created => stats.created += 1,
+                DiffKind::Deleted => stats.deleted += 1,
+                DiffKind::Modified => stats.modified += 1,
+            }
+            self.dump_diff(&diff)?;
+        }
+        match self {
+            Self::Csv(mut writer) => writer.flush()?,
+            Self::Jsonl(mut writer) => writer.flush()?,
+            Self::Sqlite {
+                db,
+                db_path,
+                mut db_temp_guard,
+            } => {
+                db.execute_batch("COMMIT")?;
+                db.close().map_err(|(_, e)| e)?;
+                db_temp_guard.promote(db_path)?;
+            }
+        }
+        Ok(stats)
+    }
+
+    fn dump_diff(&mut self, diff: &AccountDiff) -> Result<()> {
+        match self {
+            Self::Csv(writer) => {
+                let data_ranges = diff
+                    .data_ranges
+                    .iter()
+                    .map(|r| format!("{}..{}", r.start, r.end))
+                    .collect::<Vec<_>>()
+                    .join(";");
+                writer.serialize(Record::new(diff, data_ranges))?;
+            }
+            Self::Jsonl(writer) => {
+                let data_ranges = diff
+                    .data_ranges
+                    .iter()
+                    .map(|r| (r.start, r.end))
+                    .collect::<Vec<_>>();
+                serde_json::to_writer(&mut *writer, &Record::new(diff, data_ranges))?;
+                writer.write_all(b"\n")?;
+            }
+            Self::Sqlite { db, .. } => {
+                let old = diff.old.as_ref();
+                let new = diff.new.as_ref();
+                let pubkey = diff.pubkey();
+                let mut insert = db.prepare_cached(
+                    "\
+INSERT INTO account_diff (pubkey, change, old_lamports, new_lamports, lamports_delta, old_owner, new_owner, old_data_len, new_data_len, old_executable, new_executable)
+    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);",
+                )?;
+                insert.insert(params![
+                    pubkey.as_ref(),
+                    diff.kind.as_str(),
+                    old.map(|a| a.account_meta.lamports as i64),
+                    new.map(|a| a.account_meta.lamports as i64),
+                    diff.lamports_delta() as i64,
+                    old.map(|a| a.account_meta.owner.to_bytes()),
+                    new.map(|a| a.account_meta.owner.to_bytes()),
+                    old.map(|a| a.meta.data_len as i64),
+                    new.map(|a| a.meta.data_len as i64),
+                    old.map(|a| a.account_meta.executable),
+                    new.map(|a| a.account_meta.executable),
+                ])?;
+                let mut insert = db.prepare_cached(
+                    "\
+INSERT INTO account_diff_data_range (pubkey, start_offset, end_offset)
+    VALUES (?, ?, ?);",
+                )?;
+                for range in &diff.data_ranges {
+                    insert.insert(params![
+                        pubkey.as_ref(),
+                        range.start as i64,
+                        range.end as i64
+                    ])?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use clap::Args;
+use solana_sdk::pubkey::Pubkey;
+use solana_snapshot_etl::filter::AccountFilter;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// Account filters applied while reading the snapshot.
+#[derive(Args, Debug)]
+pub(crate) struct FilterArgs {
+    #[clap(
+        long = "owner",
+        value_name = "PUBKEY",
+        help = "Only include accounts owned by this program (repeatable)"
+    )]
+    owners: Vec<Pubkey>,
+    #[clap(
+        long = "pubkey",
+        value_name = "PUBKEY",
+        help = "Only include the account at this address (repeatable)"
+    )]
+    pubkeys: Vec<Pubkey>,
+    #[clap(
+        long,
+        value_name = "MIN..MAX",
+        value_parser = parse_range,
+        help = "Only include accounts with a data length in this range"
+    )]
+    data_size: Option<RangeInclusive<u64>>,
+    #[clap(
+        long,
+        value_name = "OFFSET:BASE58",
+        value_parser = parse_memcmp,
+        help = "Only include accounts with these bytes at this data offset (repeatable)"
+    )]
+    memcmp: Vec<AccountFilter>,
+    #[clap(
+        long,
+        value_name = "MIN..MAX",
+        value_parser = parse_range,
+        help = "Only include accounts with a balance in this range"
+    )]
+    lamports: Option<RangeInclusive<u64>>,
+    #[clap(
+        long,
+        value_name = "BOOL",
+        help = "Only include executable (true) or non-executable (false) accounts"
+    )]
+    executable: Option<bool>,
+    #[clap(
+        long,
+        value_name = "BOOL",
+        help = "Only include accounts with (true) or without (false) a zero balance"
+    )]
+    zero_lamport: Option<bool>,
+}
+
+impl FilterArgs {
+    pub(crate) fn account_filter(&self) -> AccountFilter {
+        let mut filter = AccountFilter::All;
+        if !self.owners.is_empty() {
+            filter = filter.and(AccountFilter::Owner(self.owners.iter().copied().collect()));
+        }
+        if !self.pubkeys.is_empty() {
+            filter = filter.and(AccountFilter::Pubkey(
+                self.pubkeys.iter().copied().collect(),
+            ));
+        }
+        if let Some(range) = &self.data_size {
+            filter = filter.and(AccountFilter::DataSize(range.clone()));
+        }
+        for memcmp in &self.memcmp {
+            filter = filter.and(memcmp.clone());
+        }
+        if let Some(range) = &self.lamports {
+            filter = filter.and(AccountFilter::Lamports(range.clone()));
+        }
+        if let Some(executable) = self.executable {
+            filter = filter.and(AccountFilter::Executable(executable));
+        }
+        if let Some(zero_lamport) = self.zero_lamport {
+            filter = filter.and(AccountFilter::ZeroLamport(zero_lamport));
+        }
+        filter
+    }
+}
+
+/// Parses an inclusive range in the form `N`, `MIN..MAX`, `MIN..` or `..MAX`.
+fn parse_range(s: &str) -> Result<RangeInclusive<u64>, String> {
+    let parse = |s: &str, default: u64| {
+        if s.is_empty() {
+            Ok(default)
+        } else {
+            u64::from_str(s).map_err(|e| format!("invalid number {:?}: {}", s, e))
+        }
+    };
+    match s.split_once("..") {
+        Some((min, max)) => Ok(parse(min, 0)?..=parse(max, u64::MAX)?),
+        None => {
+            let n = parse(s, 0)?;
+            Ok(n..=n)
+        }
+    }
+}
+
+/// Parses a memcmp filter in the form `OFFSET:BASE58`.
+fn parse_memcmp(s: &str) -> Result<AccountFilter, String> {
+    let (offset, bytes) = s
+        .split_once(':')
+        .ok_or_else(|| "expected OFFSET:BASE58".to_string())?;
+    let offset = usize::from_str(offset).map_err(|e| format!("invalid offset: {}", e))?;
+    let bytes = bs58::decode(bytes)
+        .into_vec()
+        .map_err(|e| format!("invalid base58 bytes: {}", e))?;
+    Ok(AccountFilter::Memcmp { offset, bytes })
+}
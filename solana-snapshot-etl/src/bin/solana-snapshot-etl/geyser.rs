@@ -4,31 +4,20 @@ use indicatif::{ProgressBar, ProgressStyle};
 use solana_geyser_plugin_interface::geyser_plugin_interface::{
     GeyserPlugin, ReplicaAccountInfoV2, ReplicaAccountInfoVersions,
 };
-use solana_snapshot_etl::append_vec::{AppendVec, StoredAccountMeta};
-use solana_snapshot_etl::append_vec_iter;
-use solana_snapshot_etl::parallel::{AppendVecConsumer, GenericResult};
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use solana_snapshot_etl::dedup::LatestAccount;
+use solana_snapshot_etl::parallel::GenericResult;
 use std::error::Error;
-use std::rc::Rc;
 
 pub(crate) struct GeyserDumper {
     accounts_spinner: ProgressBar,
     plugin: Box<dyn GeyserPlugin>,
+    slot: u64,
     accounts_count: u64,
 }
 
-impl AppendVecConsumer for GeyserDumper {
-    fn on_append_vec(&mut self, append_vec: AppendVec) -> GenericResult<()> {
-        for account in append_vec_iter(Rc::new(append_vec)) {
-            let account = account.access().unwrap();
-            self.dump_account(account)?;
-            std::thread::sleep(std::time::Duration::from_micros(300));
-        }
-        Ok(())
-    }
-}
-
 impl GeyserDumper {
-    pub(crate) fn new(plugin: Box<dyn GeyserPlugin>) -> Self {
+    pub(crate) fn new(plugin: Box<dyn GeyserPlugin>, slot: u64) -> Self {
         // TODO dedup spinner definitions
         let spinner_style = ProgressStyle::with_template(
             "{prefix:>10.bold.dim} {spinner} rate={per_sec}/s total={human_pos}",
@@ -41,15 +30,26 @@ impl GeyserDumper {
         Self {
             accounts_spinner,
             plugin,
+            slot,
             accounts_count: 0,
         }
     }
 
+    pub(crate) fn dump_accounts<I>(&mut self, accounts: I) -> GenericResult<()>
+    where
+        I: Iterator<Item = solana_snapshot_etl::Result<LatestAccount>>,
+    {
+        for account in accounts {
+            self.dump_account(account?.access())?;
+            std::thread::sleep(std::time::Duration::from_micros(300));
+        }
+        Ok(())
+    }
+
     pub(crate) fn dump_account(
         &mut self,
         account: StoredAccountMeta,
     ) -> Result<(), Box<dyn Error>> {
-        let slot = 165126733u64; // TODO fix slot number
         self.plugin.update_account(
             ReplicaAccountInfoVersions::V0_0_2(&ReplicaAccountInfoV2 {
                 pubkey: account.meta.pubkey.as_ref(),
@@ -61,7 +61,7 @@ impl GeyserDumper {
                 write_version: account.meta.write_version,
                 txn_signature: None,
             }),
-            slot,
+            self.slot,
             /* is_startup */ false,
         )?;
         self.accounts_count += 1;
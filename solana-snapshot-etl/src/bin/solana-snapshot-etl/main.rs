@@ -1,37 +1,84 @@
 use crate::csv::CsvDumper;
+use crate::diff::{DiffDumper, DiffFormat};
+use crate::filter::FilterArgs;
 use crate::geyser::GeyserDumper;
 use crate::geyser_plugin::load_plugin;
 use crate::programs::ProgramDumper;
 use crate::sqlite::SqliteIndexer;
+use crate::stakes::dump_stakes_csv;
+use crate::status_cache::StatusCacheDumper;
 use clap::{ArgGroup, Parser};
 use indicatif::{ProgressBar, ProgressBarIter, ProgressStyle};
-use log::{error, info};
-use reqwest::blocking::Response;
+use log::{error, info, warn};
+use solana_runtime::snapshot_archive_info::{
+    FullSnapshotArchiveInfo, IncrementalSnapshotArchiveInfo, SnapshotArchiveInfoGetter,
+};
+use solana_sdk::hash::{Hash, Hasher};
+use solana_sdk::pubkey::Pubkey;
 use solana_snapshot_etl::archived::ArchiveSnapshotExtractor;
-use solana_snapshot_etl::parallel::AppendVecConsumer;
+use solana_snapshot_etl::checkpoint::{Checkpoint, CheckpointedDeduplicator};
+use solana_snapshot_etl::decompress::ArchiveDecoder;
+use solana_snapshot_etl::dedup::{AccountDeduplicator, LatestAccounts};
+use solana_snapshot_etl::diff::diff_accounts;
+use solana_snapshot_etl::filter::{AccountFilter, FilteredAccount};
+use solana_snapshot_etl::http::{HttpSource, RetryPolicy};
+use solana_snapshot_etl::incremental::IncrementalSnapshotExtractor;
+use solana_snapshot_etl::index::AccountIndex;
+use solana_snapshot_etl::manifest::SnapshotManifest;
+use solana_snapshot_etl::s3::{parse_s3_url, S3Client};
+use solana_snapshot_etl::status_cache::SlotStatuses;
 use solana_snapshot_etl::unpacked::UnpackedSnapshotExtractor;
-use solana_snapshot_etl::{AppendVecIterator, ReadProgressTracking, SnapshotExtractor};
+use solana_snapshot_etl::verify::{compute_accounts_hash, verify_account_hash};
+use solana_snapshot_etl::writer::SnapshotWriter;
+use solana_snapshot_etl::{
+    AppendVecIterator, CorruptionPolicy, FilteredAccountCallback, ReadProgressTracking,
+    SnapshotExtractor,
+};
+use std::collections::HashSet;
 use std::fs::{File, OpenOptions};
 use std::io::{stdout, IoSliceMut, Read, Write};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 mod csv;
+mod diff;
+mod filter;
 mod geyser;
 mod geyser_plugin;
 mod mpl_metadata;
 mod programs;
 mod sqlite;
+mod stakes;
+mod status_cache;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 #[clap(group(
     ArgGroup::new("action")
         .required(true)
-        .args(&["csv", "geyser", "sqlite-out", "programs-out"]),
+        .args(&[
+            "csv",
+            "geyser",
+            "sqlite-out",
+            "programs-out",
+            "status-cache-out",
+            "stakes-csv",
+            "index-out",
+            "snapshot-out",
+            "unpack-out",
+            "get-account",
+            "diff",
+            "verify",
+        ]),
 ))]
 struct Args {
     #[clap(help = "Snapshot source (unpacked snapshot, archive file, or HTTP link)")]
     source: String,
+    #[clap(
+        long,
+        help = "Incremental snapshot source to apply on top of the full snapshot source"
+    )]
+    incremental: Option<String>,
     #[clap(long, action, help = "Write CSV to stdout")]
     csv: bool,
     #[clap(long, help = "Export to new SQLite3 DB at this path")]
@@ -40,10 +87,111 @@ struct Args {
     sqlite_cache_size: Option<i64>,
     #[clap(long, action, help = "Index token program data")]
     tokens: bool,
+    #[clap(
+        long,
+        help = "Directory for temporary files, such as spilled AppendVecs and accounts (default: system temp dir)"
+    )]
+    spill_dir: Option<String>,
+    #[clap(
+        long,
+        default_value_t = 4096,
+        help = "Memory budget in MB for account deduplication before spilling to disk"
+    )]
+    dedup_mem_mb: usize,
+    #[clap(
+        long,
+        default_value_t = num_cpus::get(),
+        help = "Threads for decompressing multi-frame zstd archives, 0 decompresses on the reading thread"
+    )]
+    decompress_threads: usize,
+    #[clap(
+        long,
+        action,
+        help = "Log and skip corrupt AppendVec records instead of failing"
+    )]
+    lenient: bool,
+    #[clap(long, action, help = "Verify the stored hash of every account")]
+    verify_hashes: bool,
+    #[clap(
+        long,
+        action,
+        help = "Verify the accounts hash against the snapshot manifest and archive name"
+    )]
+    verify: bool,
     #[clap(long, help = "Load Geyser plugin from given config file")]
     geyser: Option<String>,
     #[clap(long, help = "Write programs tar stream")]
     programs_out: Option<String>,
+    #[clap(
+        long,
+        help = "Write stake delegations, vote accounts and epoch stakes as CSV files to this directory"
+    )]
+    stakes_csv: Option<String>,
+    #[clap(long, help = "Write status cache transaction statuses as CSV")]
+    status_cache_out: Option<String>,
+    #[clap(
+        long,
+        help = "Write the (filtered) accounts as a new snapshot archive into this directory"
+    )]
+    snapshot_out: Option<String>,
+    #[clap(
+        long,
+        help = "Unpack a snapshot archive (keeping only the filtered accounts) into this directory"
+    )]
+    unpack_out: Option<String>,
+    #[clap(
+        long,
+        help = "Build a pubkey index of an unpacked snapshot for account lookups at this path"
+    )]
+    index_out: Option<String>,
+    #[clap(
+        long,
+        value_name = "PUBKEY",
+        requires = "index",
+        help = "Look up accounts of an unpacked snapshot and write them as CSV to stdout (repeatable)"
+    )]
+    get_account: Vec<Pubkey>,
+    #[clap(long, help = "Pubkey index built with --index-out")]
+    index: Option<String>,
+    #[clap(
+        long,
+        value_name = "SOURCE",
+        help = "Diff the accounts of the snapshot source against this newer snapshot source"
+    )]
+    diff: Option<String>,
+    #[clap(
+        long,
+        value_enum,
+        default_value = "csv",
+        help = "Output format of --diff"
+    )]
+    diff_format: DiffFormat,
+    #[clap(
+        long,
+        default_value = "-",
+        help = "Output path of --diff, \"-\" writes CSV and JSON lines to stdout"
+    )]
+    diff_out: String,
+    #[clap(
+        long,
+        help = "Record the progress of --sqlite-out and --geyser runs in this checkpoint file"
+    )]
+    checkpoint: Option<String>,
+    #[clap(
+        long,
+        action,
+        requires = "checkpoint",
+        help = "Resume an interrupted run from the checkpoint file, if it exists"
+    )]
+    resume: bool,
+    #[clap(
+        long,
+        action,
+        help = "Report archive read progress as JSON lines on stderr instead of a progress bar"
+    )]
+    progress_json: bool,
+    #[clap(flatten)]
+    filter: FilterArgs,
 }
 
 fn main() {
@@ -58,86 +206,472 @@ fn main() {
 
 fn _main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
-    let mut loader = SupportedLoader::new(&args.source, Box::new(LoadProgressTracking {}))?;
+    let spill_dir = args
+        .spill_dir
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    let mut loader = SupportedLoader::new(
+        &args.source,
+        &spill_dir,
+        args.decompress_threads,
+        Box::new(LoadProgressTracking {
+            json: args.progress_json,
+        }),
+    )?;
+    if let Some(incremental_source) = &args.incremental {
+        check_incremental_base(incremental_source, loader.manifest().slot())?;
+        let incremental_loader = SupportedLoader::new(
+            incremental_source,
+            &spill_dir,
+            args.decompress_threads,
+            Box::new(LoadProgressTracking {
+                json: args.progress_json,
+            }),
+        )?;
+        loader = SupportedLoader::Incremental(Box::new(IncrementalSnapshotExtractor::new(
+            loader,
+            incremental_loader,
+        )?));
+    }
+    let manifest = loader.manifest();
+    info!(
+        "Snapshot at slot {} (epoch {}, block height {}, bank hash {})",
+        manifest.slot(),
+        manifest.epoch(),
+        manifest.block_height(),
+        manifest.bank_hash()
+    );
+    let slot = manifest.slot();
+    let filter = args.filter.account_filter();
+    if args
+        .sqlite_out
+        .as_ref()
+        .is_some_and(|p| Path::new(p).exists())
+    {
+        return Err("Refusing to overwrite database that already exists".into());
+    }
+    let mut checkpoint = match &args.checkpoint {
+        Some(_) if args.sqlite_out.is_none() && args.geyser.is_none() => {
+            return Err("--checkpoint is only supported with --sqlite-out and --geyser".into());
+        }
+        Some(path) if args.resume && Path::new(path).exists() => Some(Checkpoint::open(
+            Path::new(path),
+            manifest,
+            checkpoint_options(&args, &filter),
+        )?),
+        Some(path) => {
+            if Path::new(path).exists() {
+                return Err("Checkpoint already exists, pass --resume to continue from it".into());
+            }
+            Some(Checkpoint::create(
+                Path::new(path),
+                manifest,
+                checkpoint_options(&args, &filter),
+            )?)
+        }
+        None => None,
+    };
+    if args.verify {
+        if filter != AccountFilter::All {
+            return Err("Account filters cannot be combined with --verify".into());
+        }
+        if args.incremental.is_some()
+            && manifest
+                .extra_fields
+                .incremental_snapshot_persistence
+                .is_some()
+        {
+            // Such incremental snapshots only hash the accounts changed since the full snapshot.
+            return Err(
+                "--verify is not supported for incremental snapshots with an incremental accounts hash"
+                    .into(),
+            );
+        }
+        let expected = *manifest.accounts_hash();
+        let archive_source = args.incremental.as_deref().unwrap_or(&args.source);
+        if let Some(name_hash) = archive_name_hash(archive_source) {
+            if name_hash != manifest.archive_hash() {
+                return Err(format!(
+                    "Archive name has hash {}, but manifest has {}",
+                    name_hash,
+                    manifest.archive_hash()
+                )
+                .into());
+            }
+        }
+        info!("Verifying accounts hash");
+        let accounts = latest_accounts(&mut loader, &args, &filter, &spill_dir, None)?;
+        let computed = compute_accounts_hash(accounts)?;
+        if computed != expected {
+            return Err(format!(
+                "Computed accounts hash {}, but manifest has {}",
+                computed, expected
+            )
+            .into());
+        }
+        info!("Accounts hash {} verified", computed);
+    }
     if args.csv {
         info!("Dumping to CSV");
+        let accounts = latest_accounts(&mut loader, &args, &filter, &spill_dir, None)?;
         let mut writer = CsvDumper::new();
-        for append_vec in loader.iter() {
-            writer.dump_append_vec(append_vec?);
-        }
+        writer.dump_accounts(accounts)?;
         drop(writer);
         println!("Done!");
     }
-    if let Some(geyser_config_path) = args.geyser {
-        info!("Dumping to Geyser plugin: {}", &geyser_config_path);
-        let plugin = unsafe { load_plugin(&geyser_config_path)? };
+    if let Some(geyser_config_path) = &args.geyser {
+        info!("Dumping to Geyser plugin: {}", geyser_config_path);
+        let plugin = unsafe { load_plugin(geyser_config_path)? };
         assert!(
             plugin.account_data_notifications_enabled(),
             "Geyser plugin does not accept account data notifications"
         );
-        let mut dumper = GeyserDumper::new(plugin);
-        for append_vec in loader.iter() {
-            dumper.on_append_vec(append_vec?)?;
+        let accounts =
+            latest_accounts(&mut loader, &args, &filter, &spill_dir, checkpoint.as_mut())?;
+        let mut dumper = GeyserDumper::new(plugin, slot);
+        match &mut checkpoint {
+            Some(checkpoint) => dumper.dump_accounts(checkpoint.track_sink(accounts))?,
+            None => dumper.dump_accounts(accounts)?,
         }
         drop(dumper);
         println!("Done!");
     }
-    if let Some(sqlite_out_path) = args.sqlite_out {
-        info!("Dumping to SQLite3: {}", &sqlite_out_path);
+    if let Some(sqlite_out_path) = &args.sqlite_out {
+        info!("Dumping to SQLite3: {}", sqlite_out_path);
         let db_path = PathBuf::from(sqlite_out_path);
-        if db_path.exists() {
-            return Err("Refusing to overwrite database that already exists".into());
-        }
-
-        let mut indexer = SqliteIndexer::new(db_path)?;
+        let mut indexer = match &mut checkpoint {
+            Some(checkpoint) => {
+                let (indexer, resumed) = SqliteIndexer::new_resumable(db_path, args.resume)?;
+                if !resumed {
+                    checkpoint.reset_sink()?;
+                }
+                indexer
+            }
+            None => SqliteIndexer::new(db_path)?,
+        };
         if let Some(cache_size) = args.sqlite_cache_size {
             indexer.set_cache_size(cache_size)?;
         }
-        let stats = indexer.insert_all(loader.iter())?;
+        indexer.insert_stakes(loader.manifest())?;
+        let accounts =
+            latest_accounts(&mut loader, &args, &filter, &spill_dir, checkpoint.as_mut())?;
+        let stats = match &mut checkpoint {
+            Some(checkpoint) => indexer.insert_all(checkpoint.track_sink(accounts))?,
+            None => indexer.insert_all(accounts)?,
+        };
 
         info!("Done!");
         info!("Dumped {} accounts", stats.accounts_total);
         info!("Dumped {} token accounts", stats.token_accounts_total);
     }
-    if let Some(programs) = args.programs_out {
-        info!("Dumping program accounts to {}", &programs);
-        let writer: Box<dyn Write> = if programs == "-" {
-            Box::new(stdout())
-        } else {
-            Box::new(
-                OpenOptions::new()
-                    .write(true)
-                    .create_new(true)
-                    .open(programs)?,
-            )
-        };
+    if let Some(programs) = &args.programs_out {
+        info!("Dumping program accounts to {}", programs);
+        let writer = open_output(programs)?;
+        let program_filter = filter.clone().and(ProgramDumper::account_filter());
+        let accounts = latest_accounts(&mut loader, &args, &program_filter, &spill_dir, None)?;
         let mut dumper = ProgramDumper::new(writer);
-        for append_vec in loader.iter() {
-            dumper.on_append_vec(append_vec?)?;
-        }
+        dumper.dump_accounts(accounts)?;
         drop(dumper);
         info!("Done!");
     }
+    if let Some(snapshot_out) = &args.snapshot_out {
+        info!("Writing snapshot to {}", snapshot_out);
+        // The bank cannot be restored without builtins and sysvars, so keep them regardless.
+        let filter = AccountFilter::Or(vec![filter.clone(), SnapshotWriter::required_accounts()]);
+        let accounts = latest_accounts(&mut loader, &args, &filter, &spill_dir, None)?;
+        let mut writer = SnapshotWriter::new(loader.manifest().clone(), &spill_dir)?;
+        writer.push_all(accounts)?;
+        let status_cache = loader.status_cache()?.unwrap_or_else(|| {
+            warn!("No status cache found, writing an empty one");
+            Vec::new()
+        });
+        let path = writer.finish(Path::new(snapshot_out), &status_cache)?;
+        info!("Done!");
+        info!("Wrote {:?}", path);
+    }
+    if let Some(unpack_out) = &args.unpack_out {
+        info!("Unpacking snapshot to {}", unpack_out);
+        let stats = match &mut loader {
+            SupportedLoader::ArchiveFile(loader) | SupportedLoader::ArchiveDownload(loader) => {
+                loader.unpack(Path::new(unpack_out), filter.clone())?
+            }
+            _ => return Err("Unpacking requires a snapshot archive".into()),
+        };
+        info!("Done!");
+        info!(
+            "Wrote {} accounts in {} AppendVecs",
+            stats.accounts, stats.append_vecs
+        );
+    }
+    if let Some(index_out) = &args.index_out {
+        info!("Building account index at {}", index_out);
+        let index = match &mut loader {
+            SupportedLoader::Unpacked(loader) => AccountIndex::build(
+                loader,
+                corruption_policy(&args),
+                Path::new(index_out),
+                &spill_dir,
+                args.dedup_mem_mb * 1024 * 1024,
+            )?,
+            _ => return Err("Account index requires an unpacked snapshot".into()),
+        };
+        info!("Done!");
+        info!("Indexed {} accounts", index.len());
+    }
+    if let Some(index_path) = args.index.as_ref().filter(|_| !args.get_account.is_empty()) {
+        let loader = match &loader {
+            SupportedLoader::Unpacked(loader) => loader,
+            _ => return Err("Account lookups require an unpacked snapshot".into()),
+        };
+        let index = AccountIndex::open(Path::new(index_path))?;
+        let accounts = loader.get_multiple_accounts(&index, &args.get_account)?;
+        let mut writer = CsvDumper::new();
+        for (pubkey, account) in args.get_account.iter().zip(accounts) {
+            match account {
+                Some(account) => writer.dump_account(account.access()),
+                None => info!("Account {} not found", pubkey),
+            }
+        }
+    }
+    if let Some(stakes_csv) = &args.stakes_csv {
+        info!("Dumping stakes to {}", stakes_csv);
+        dump_stakes_csv(loader.manifest(), Path::new(stakes_csv))?;
+        info!("Done!");
+    }
+    if let Some(status_cache_out) = &args.status_cache_out {
+        info!("Dumping status cache to {}", status_cache_out);
+        let writer = open_output(status_cache_out)?;
+        let status_cache = match loader.status_cache()? {
+            Some(status_cache) => status_cache,
+            None => {
+                info!("Status cache is stored after the accounts, reading through snapshot");
+                loader.for_each_account(corruption_policy(&args), &mut |_, _, _| Ok(()))?;
+                loader
+                    .status_cache()?
+                    .ok_or(solana_snapshot_etl::SnapshotError::NoStatusCache)?
+            }
+        };
+        let mut dumper = StatusCacheDumper::new(writer);
+        let stats = dumper.dump_status_cache(&status_cache)?;
+        info!("Done!");
+        info!(
+            "Dumped {} transaction statuses from {} slots",
+            stats.statuses_total, stats.slots_total
+        );
+    }
+    if let Some(diff_source) = &args.diff {
+        info!("Diffing accounts against {}", diff_source);
+        let mut new_loader = SupportedLoader::new(
+            diff_source,
+            &spill_dir,
+            args.decompress_threads,
+            Box::new(LoadProgressTracking {
+                json: args.progress_json,
+            }),
+        )?;
+        info!("New snapshot at slot {}", new_loader.manifest().slot());
+        let dumper = match args.diff_format {
+            DiffFormat::Csv => DiffDumper::new_csv(open_output(&args.diff_out)?),
+            DiffFormat::Jsonl => DiffDumper::new_jsonl(open_output(&args.diff_out)?),
+            DiffFormat::Sqlite => {
+                let db_path = PathBuf::from(&args.diff_out);
+                if args.diff_out == "-" {
+                    return Err("SQLite3 diff output requires a --diff-out path".into());
+                }
+                if db_path.exists() {
+                    return Err("Refusing to overwrite database that already exists".into());
+                }
+                DiffDumper::new_sqlite(db_path)?
+            }
+        };
+        let old_accounts = latest_accounts(&mut loader, &args, &filter, &spill_dir, None)?;
+        let new_accounts = latest_accounts(&mut new_loader, &args, &filter, &spill_dir, None)?;
+        let stats = dumper.dump_diffs(diff_accounts(old_accounts, new_accounts))?;
+        info!("Done!");
+        info!(
+            "{} accounts created, {} deleted, {} modified",
+            stats.created, stats.deleted, stats.modified
+        );
+    }
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.remove()?;
+    }
+    Ok(())
+}
+
+/// Opens a new output file, or stdout if the path is "-".
+fn open_output(path: &str) -> std::io::Result<Box<dyn Write>> {
+    if path == "-" {
+        return Ok(Box::new(stdout()));
+    }
+    let file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    Ok(Box::new(file))
+}
+
+/// Fingerprints the options that change which accounts a checkpointed run writes.
+fn checkpoint_options(args: &Args, filter: &AccountFilter) -> Hash {
+    let mut hasher = Hasher::default();
+    hasher.hash(filter.fingerprint().as_ref());
+    hasher.hash(&[args.verify_hashes as u8, args.lenient as u8]);
+    hasher.result()
+}
+
+fn corruption_policy(args: &Args) -> CorruptionPolicy {
+    if args.lenient {
+        CorruptionPolicy::Lenient
+    } else {
+        CorruptionPolicy::Strict
+    }
+}
+
+/// Checks the base slot in the incremental snapshot archive name, if it has one.
+fn check_incremental_base(source: &str, full_slot: u64) -> Result<(), Box<dyn std::error::Error>> {
+    let file_name = source.rsplit('/').next().unwrap_or(source);
+    if let Ok(info) = IncrementalSnapshotArchiveInfo::new_from_path(PathBuf::from(file_name)) {
+        if info.base_slot() != full_slot {
+            return Err(format!(
+                "Incremental snapshot is based on slot {}, but full snapshot is at slot {}",
+                info.base_slot(),
+                full_slot
+            )
+            .into());
+        }
+    }
     Ok(())
 }
 
-struct LoadProgressTracking {}
+/// Returns the accounts hash in the snapshot archive name, if it has one.
+fn archive_name_hash(source: &str) -> Option<Hash> {
+    let file_name = PathBuf::from(source.rsplit('/').next().unwrap_or(source));
+    if let Ok(info) = FullSnapshotArchiveInfo::new_from_path(file_name.clone()) {
+        return Some(*info.hash());
+    }
+    IncrementalSnapshotArchiveInfo::new_from_path(file_name)
+        .ok()
+        .map(|info| *info.hash())
+}
+
+fn latest_accounts(
+    loader: &mut SupportedLoader,
+    args: &Args,
+    filter: &AccountFilter,
+    spill_dir: &Path,
+    checkpoint: Option<&mut Checkpoint>,
+) -> Result<LatestAccounts, Box<dyn std::error::Error>> {
+    let mem_budget = args.dedup_mem_mb * 1024 * 1024;
+    let mut dedup = match checkpoint {
+        Some(checkpoint) => {
+            loader.skip_append_vecs(checkpoint.append_vecs().clone());
+            Deduplicator::Checkpointed(CheckpointedDeduplicator::new(checkpoint, mem_budget)?)
+        }
+        None => Deduplicator::Plain(AccountDeduplicator::new(spill_dir, mem_budget)?),
+    };
+    dedup.keep_deleted(filter.selects_zero_lamport());
+    if dedup.is_done() {
+        info!("Accounts already deduplicated");
+        return Ok(dedup.finish()?);
+    }
+    info!("Deduplicating accounts");
+    let mut hash_mismatches = 0u64;
+    loader.for_each_filtered_account(
+        corruption_policy(args),
+        filter,
+        &mut |slot, id, account| {
+            if let (true, FilteredAccount::Matched(account)) = (args.verify_hashes, &account) {
+                if let Some(mismatch) = verify_account_hash(slot, id, account) {
+                    error!("{}", mismatch);
+                    hash_mismatches += 1;
+                }
+            }
+            dedup.insert_filtered(slot, id, account)
+        },
+    )?;
+    let accounts = dedup.finish()?;
+    if hash_mismatches > 0 {
+        return Err(format!("{} accounts failed hash verification", hash_mismatches).into());
+    }
+    Ok(accounts)
+}
+
+/// Deduplicator that optionally records its progress in a checkpoint.
+enum Deduplicator<'a> {
+    Plain(AccountDeduplicator),
+    Checkpointed(CheckpointedDeduplicator<'a>),
+}
+
+impl<'a> Deduplicator<'a> {
+    fn is_done(&self) -> bool {
+        match self {
+            Deduplicator::Plain(_) => false,
+            Deduplicator::Checkpointed(dedup) => dedup.is_done(),
+        }
+    }
+
+    fn keep_deleted(&mut self, keep_deleted: bool) {
+        match self {
+            Deduplicator::Plain(dedup) => dedup.keep_deleted(keep_deleted),
+            Deduplicator::Checkpointed(dedup) => dedup.keep_deleted(keep_deleted),
+        }
+    }
+
+    fn insert_filtered(
+        &mut self,
+        slot: u64,
+        id: u64,
+        account: FilteredAccount,
+    ) -> solana_snapshot_etl::Result<()> {
+        match self {
+            Deduplicator::Plain(dedup) => dedup.insert_filtered(slot, id, account),
+            Deduplicator::Checkpointed(dedup) => dedup.insert_filtered(slot, id, account),
+        }
+    }
+
+    fn finish(self) -> solana_snapshot_etl::Result<LatestAccounts> {
+        match self {
+            Deduplicator::Plain(dedup) => dedup.finish(),
+            Deduplicator::Checkpointed(dedup) => dedup.finish(),
+        }
+    }
+}
+
+struct LoadProgressTracking {
+    json: bool,
+}
 
 impl ReadProgressTracking for LoadProgressTracking {
     fn new_read_progress_tracker(
         &self,
-        _: &Path,
+        path: &Path,
         rd: Box<dyn Read>,
         file_len: u64,
     ) -> Box<dyn Read> {
-        let progress_bar = ProgressBar::new(file_len).with_style(
-            ProgressStyle::with_template(
-                "{prefix:>10.bold.dim} {spinner:.green} [{bar:.cyan/blue}] {bytes}/{total_bytes} ({percent}%)",
+        let name = path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .into_owned();
+        if self.json {
+            return Box::new(JsonProgressTracker::new(rd, name, file_len));
+        }
+        let progress_bar = if file_len > 0 {
+            ProgressBar::new(file_len).with_style(
+                ProgressStyle::with_template(
+                    "{prefix:>10.bold.dim} {spinner:.green} [{bar:.cyan/blue}] {bytes}/{total_bytes} ({percent}%, {binary_bytes_per_sec}, ETA {eta}) {msg}",
+                )
+                .unwrap()
+                .progress_chars("#>-"),
             )
-            .unwrap()
-            .progress_chars("#>-"),
-        );
-        progress_bar.set_prefix("manifest");
+        } else {
+            ProgressBar::new_spinner().with_style(
+                ProgressStyle::with_template(
+                    "{prefix:>10.bold.dim} {spinner:.green} {bytes} ({binary_bytes_per_sec}) {msg}",
+                )
+                .unwrap(),
+            )
+        };
+        progress_bar.set_prefix("reading");
+        progress_bar.set_message(name);
         Box::new(LoadProgressTracker {
             rd: progress_bar.wrap_read(rd),
             progress_bar,
@@ -145,6 +679,76 @@ impl ReadProgressTracking for LoadProgressTracking {
     }
 }
 
+/// Reports read progress as JSON lines on stderr, once per `JSON_PROGRESS_INTERVAL` and when done.
+struct JsonProgressTracker {
+    rd: Box<dyn Read>,
+    source: String,
+    total_bytes: u64,
+    bytes_read: u64,
+    start: Instant,
+    last_report: Instant,
+}
+
+const JSON_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+impl JsonProgressTracker {
+    fn new(rd: Box<dyn Read>, source: String, total_bytes: u64) -> Self {
+        let now = Instant::now();
+        let tracker = Self {
+            rd,
+            source,
+            total_bytes,
+            bytes_read: 0,
+            start: now,
+            last_report: now,
+        };
+        tracker.report(false);
+        tracker
+    }
+
+    fn report(&self, done: bool) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let bytes_per_sec = if elapsed > 0.0 {
+            self.bytes_read as f64 / elapsed
+        } else {
+            0.0
+        };
+        let known_total = (self.total_bytes > 0).then_some(self.total_bytes);
+        let eta_secs = known_total
+            .filter(|_| bytes_per_sec > 0.0)
+            .map(|total| total.saturating_sub(self.bytes_read) as f64 / bytes_per_sec);
+        let event = serde_json::json!({
+            "event": if done { "read_done" } else { "read_progress" },
+            "source": self.source,
+            "bytes_read": self.bytes_read,
+            "total_bytes": known_total,
+            "percent": known_total.map(|total| 100.0 * self.bytes_read as f64 / total as f64),
+            "bytes_per_sec": bytes_per_sec,
+            "eta_secs": eta_secs,
+            "elapsed_secs": elapsed,
+        });
+        eprintln!("{}", event);
+    }
+}
+
+impl Drop for JsonProgressTracker {
+    fn drop(&mut self) {
+        self.report(true)
+    }
+}
+
+impl Read for JsonProgressTracker {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.rd.read(buf)?;
+        self.bytes_read += n as u64;
+        if self.last_report.elapsed() >= JSON_PROGRESS_INTERVAL {
+            self.last_report = Instant::now();
+            self.report(false);
+        }
+        Ok(n)
+    }
+}
+
 struct LoadProgressTracker {
     progress_bar: ProgressBar,
     rd: ProgressBarIter<Box<dyn Read>>,
@@ -176,31 +780,78 @@ impl Read for LoadProgressTracker {
 
 pub enum SupportedLoader {
     Unpacked(UnpackedSnapshotExtractor),
-    ArchiveFile(ArchiveSnapshotExtractor<File>),
-    ArchiveDownload(ArchiveSnapshotExtractor<Response>),
+    ArchiveFile(ArchiveSnapshotExtractor<Box<dyn Read>>),
+    ArchiveDownload(ArchiveSnapshotExtractor<Box<dyn Read>>),
+    Incremental(Box<IncrementalSnapshotExtractor<SupportedLoader, SupportedLoader>>),
 }
 
 impl SupportedLoader {
     fn new(
         source: &str,
+        spill_dir: &Path,
+        decompress_threads: usize,
         progress_tracking: Box<dyn ReadProgressTracking>,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         if source.starts_with("http://") || source.starts_with("https://") {
-            Self::new_download(source)
+            Self::new_download(source, spill_dir, decompress_threads, progress_tracking)
+        } else if source.starts_with("s3://") {
+            Self::new_s3(source, spill_dir, decompress_threads, progress_tracking)
         } else {
-            Self::new_file(source.as_ref(), progress_tracking).map_err(Into::into)
+            Self::new_file(
+                source.as_ref(),
+                spill_dir,
+                decompress_threads,
+                progress_tracking,
+            )
+            .map_err(Into::into)
         }
     }
 
-    fn new_download(url: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let resp = reqwest::blocking::get(url)?;
-        let loader = ArchiveSnapshotExtractor::from_reader(resp)?;
+    fn new_download(
+        url: &str,
+        spill_dir: &Path,
+        decompress_threads: usize,
+        progress_tracking: Box<dyn ReadProgressTracking>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let source = HttpSource::open(url)?;
+        // Query strings may hold credentials, keep them out of progress output.
+        let name = url.split('?').next().unwrap_or(url);
+        let source = track_download(source, name, progress_tracking);
+        let loader = open_archive(source, spill_dir, decompress_threads)?;
         info!("Streaming snapshot from HTTP");
         Ok(Self::ArchiveDownload(loader))
     }
 
+    /// Streams `s3://<bucket>/<key>`, or the newest full snapshot under the prefix if the key ends with `/`.
+    fn new_s3(
+        url: &str,
+        spill_dir: &Path,
+        decompress_threads: usize,
+        progress_tracking: Box<dyn ReadProgressTracking>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let (bucket, key) =
+            parse_s3_url(url).ok_or("Invalid S3 URL, expected s3://<bucket>/<key>")?;
+        let client = S3Client::from_env()?;
+        let key = if key.is_empty() || key.ends_with('/') {
+            let latest = client
+                .find_latest_snapshot(bucket, key)?
+                .ok_or("No full snapshot archive found under S3 prefix")?;
+            info!("Found newest snapshot s3://{}/{}", bucket, latest.key);
+            latest.key
+        } else {
+            key.to_owned()
+        };
+        let source = client.open(bucket, &key, RetryPolicy::default())?;
+        let source = track_download(source, &key, progress_tracking);
+        let loader = open_archive(source, spill_dir, decompress_threads)?;
+        info!("Streaming snapshot from S3");
+        Ok(Self::ArchiveDownload(loader))
+    }
+
     fn new_file(
         path: &Path,
+        spill_dir: &Path,
+        decompress_threads: usize,
         progress_tracking: Box<dyn ReadProgressTracking>,
     ) -> solana_snapshot_etl::Result<Self> {
         Ok(if path.is_dir() {
@@ -208,17 +859,95 @@ impl SupportedLoader {
             Self::Unpacked(UnpackedSnapshotExtractor::open(path, progress_tracking)?)
         } else {
             info!("Reading snapshot archive");
-            Self::ArchiveFile(ArchiveSnapshotExtractor::open(path)?)
+            let file = File::open(path)?;
+            let file_len = file.metadata()?.len();
+            let source =
+                progress_tracking.new_read_progress_tracker(path, Box::new(file), file_len);
+            Self::ArchiveFile(open_archive(source, spill_dir, decompress_threads)?)
         })
     }
 }
 
+/// Reads an archive, decompressing it on background threads unless `decompress_threads` is 0.
+fn open_archive(
+    source: Box<dyn Read>,
+    spill_dir: &Path,
+    decompress_threads: usize,
+) -> solana_snapshot_etl::Result<ArchiveSnapshotExtractor<Box<dyn Read>>> {
+    let decoder = if decompress_threads > 0 {
+        ArchiveDecoder::pipelined(source, decompress_threads)?
+    } else {
+        ArchiveDecoder::new(source)?
+    };
+    ArchiveSnapshotExtractor::from_decoder(decoder, Some(spill_dir))
+}
+
+/// Wraps a download in a progress tracker, sized by its `Content-Length` if the server sent one.
+fn track_download(
+    source: HttpSource,
+    name: &str,
+    progress_tracking: Box<dyn ReadProgressTracking>,
+) -> Box<dyn Read> {
+    let len = source.content_length().unwrap_or(0);
+    progress_tracking.new_read_progress_tracker(Path::new(name), Box::new(source), len)
+}
+
 impl SnapshotExtractor for SupportedLoader {
     fn iter(&mut self) -> AppendVecIterator<'_> {
         match self {
             SupportedLoader::Unpacked(loader) => Box::new(loader.iter()),
             SupportedLoader::ArchiveFile(loader) => Box::new(loader.iter()),
             SupportedLoader::ArchiveDownload(loader) => Box::new(loader.iter()),
+            SupportedLoader::Incremental(loader) => Box::new(loader.iter()),
+        }
+    }
+
+    fn manifest(&self) -> &SnapshotManifest {
+        match self {
+            SupportedLoader::Unpacked(loader) => loader.manifest(),
+            SupportedLoader::ArchiveFile(loader) => loader.manifest(),
+            SupportedLoader::ArchiveDownload(loader) => loader.manifest(),
+            SupportedLoader::Incremental(loader) => loader.manifest(),
+        }
+    }
+
+    fn status_cache(&self) -> solana_snapshot_etl::Result<Option<Vec<SlotStatuses>>> {
+        match self {
+            SupportedLoader::Unpacked(loader) => loader.status_cache(),
+            SupportedLoader::ArchiveFile(loader) => loader.status_cache(),
+            SupportedLoader::ArchiveDownload(loader) => loader.status_cache(),
+            SupportedLoader::Incremental(loader) => loader.status_cache(),
+        }
+    }
+
+    fn skip_append_vecs(&mut self, append_vecs: HashSet<(u64, u64)>) {
+        match self {
+            SupportedLoader::Unpacked(loader) => loader.skip_append_vecs(append_vecs),
+            SupportedLoader::ArchiveFile(loader) => loader.skip_append_vecs(append_vecs),
+            SupportedLoader::ArchiveDownload(loader) => loader.skip_append_vecs(append_vecs),
+            SupportedLoader::Incremental(loader) => loader.skip_append_vecs(append_vecs),
+        }
+    }
+
+    fn for_each_filtered_account(
+        &mut self,
+        policy: CorruptionPolicy,
+        filter: &AccountFilter,
+        f: &mut FilteredAccountCallback<'_>,
+    ) -> solana_snapshot_etl::Result<()> {
+        match self {
+            SupportedLoader::Unpacked(loader) => {
+                loader.for_each_filtered_account(policy, filter, f)
+            }
+            SupportedLoader::ArchiveFile(loader) => {
+                loader.for_each_filtered_account(policy, filter, f)
+            }
+            SupportedLoader::ArchiveDownload(loader) => {
+                loader.for_each_filtered_account(policy, filter, f)
+            }
+            SupportedLoader::Incremental(loader) => {
+                loader.for_each_filtered_account(policy, filter, f)
+            }
         }
     }
 }
@@ -2,26 +2,18 @@ use bincode::Options;
 use solana_program::bpf_loader_upgradeable::UpgradeableLoaderState;
 use solana_program::pubkey::Pubkey;
 use solana_program::{bpf_loader, bpf_loader_deprecated, bpf_loader_upgradeable};
-use solana_snapshot_etl::append_vec::{AppendVec, StoredAccountMeta};
-use solana_snapshot_etl::append_vec_iter;
-use solana_snapshot_etl::parallel::{AppendVecConsumer, GenericResult};
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use solana_snapshot_etl::dedup::LatestAccounts;
+use solana_snapshot_etl::filter::AccountFilter;
+use solana_snapshot_etl::parallel::GenericResult;
+use std::collections::HashSet;
 use std::io::Write;
-use std::rc::Rc;
 use tar::{Builder, Header};
 
 pub(crate) struct ProgramDumper {
     builder: Builder<Box<dyn Write>>,
 }
 
-impl AppendVecConsumer for ProgramDumper {
-    fn on_append_vec(&mut self, append_vec: AppendVec) -> GenericResult<()> {
-        for account in append_vec_iter(Rc::new(append_vec)) {
-            self.insert_account(&account.access().unwrap())?;
-        }
-        Ok(())
-    }
-}
-
 impl ProgramDumper {
     pub(crate) fn new(writer: Box<dyn Write>) -> Self {
         Self {
@@ -29,6 +21,22 @@ impl ProgramDumper {
         }
     }
 
+    /// Matches accounts owned by one of the BPF loaders.
+    pub(crate) fn account_filter() -> AccountFilter {
+        AccountFilter::Owner(HashSet::from([
+            bpf_loader_deprecated::id(),
+            bpf_loader::id(),
+            bpf_loader_upgradeable::id(),
+        ]))
+    }
+
+    pub(crate) fn dump_accounts(&mut self, accounts: LatestAccounts) -> GenericResult<()> {
+        for account in accounts {
+            self.insert_account(&account?.access())?;
+        }
+        Ok(())
+    }
+
     pub(crate) fn insert_account(&mut self, account: &StoredAccountMeta) -> GenericResult<()> {
         if bpf_loader_deprecated::check_id(&account.account_meta.owner)
             || bpf_loader::check_id(&account.account_meta.owner)
@@ -1,13 +1,12 @@
 use borsh::BorshDeserialize;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use log::{error, warn};
+use log::{error, info, warn};
 use rusqlite::{params, Connection};
 use solana_sdk::program_pack::Pack;
-use solana_snapshot_etl::append_vec::{AppendVec, StoredAccountMeta};
-use solana_snapshot_etl::parallel::{AppendVecConsumer, GenericResult};
-use solana_snapshot_etl::{append_vec_iter, AppendVecIterator};
+use solana_snapshot_etl::append_vec::StoredAccountMeta;
+use solana_snapshot_etl::dedup::LatestAccount;
+use solana_snapshot_etl::manifest::SnapshotManifest;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 
@@ -38,14 +37,39 @@ pub(crate) struct IndexStats {
 impl SqliteIndexer {
     pub(crate) fn new(db_path: PathBuf) -> Result<Self> {
         // Create temporary DB file, which gets promoted on success.
-        let temp_file_name = format!("_{}.tmp", db_path.file_name().unwrap().to_string_lossy());
-        let db_temp_path = db_path.with_file_name(&temp_file_name);
+        let db_temp_path = Self::temp_path(&db_path);
         let _ = std::fs::remove_file(&db_temp_path);
         let db_temp_guard = TempFileGuard::new(db_temp_path.clone());
 
         // Open database.
         let db = Self::create_db(&db_temp_path)?;
+        Ok(Self::with_db(db, db_path, db_temp_guard))
+    }
+
+    /// Like `new`, but keeps the temporary DB if indexing fails.
+    ///
+    /// With `resume`, a temporary DB left behind by an earlier run is reopened.
+    /// Returns whether that was the case.
+    pub(crate) fn new_resumable(db_path: PathBuf, resume: bool) -> Result<(Self, bool)> {
+        let db_temp_path = Self::temp_path(&db_path);
+        let resumed = resume && db_temp_path.exists();
+        let db = if resumed {
+            info!("Resuming SQLite3 DB {:?}", &db_temp_path);
+            Self::open_db(&db_temp_path)?
+        } else {
+            let _ = std::fs::remove_file(&db_temp_path);
+            Self::create_db(&db_temp_path)?
+        };
+        let db_temp_guard = TempFileGuard::new_kept(db_temp_path);
+        Ok((Self::with_db(db, db_path, db_temp_guard), resumed))
+    }
 
+    fn temp_path(db_path: &Path) -> PathBuf {
+        let temp_file_name = format!("_{}.tmp", db_path.file_name().unwrap().to_string_lossy());
+        db_path.with_file_name(&temp_file_name)
+    }
+
+    fn with_db(db: Connection, db_path: PathBuf, db_temp_guard: TempFileGuard) -> Self {
         // Create progress bars.
         let spinner_style = ProgressStyle::with_template(
             "{prefix:>13.bold.dim} {spinner} rate={per_sec:>13} total={human_pos:>11}",
@@ -74,7 +98,7 @@ impl SqliteIndexer {
             ),
         );
 
-        Ok(Self {
+        Self {
             db,
             db_path,
             db_temp_guard,
@@ -85,14 +109,19 @@ impl SqliteIndexer {
                 token_accounts_counter,
                 metaplex_accounts_counter,
             }),
-        })
+        }
     }
 
-    fn create_db(path: &Path) -> Result<Connection> {
+    fn open_db(path: &Path) -> Result<Connection> {
         let db = Connection::open(&path)?;
         db.pragma_update(None, "synchronous", false)?;
         db.pragma_update(None, "journal_mode", "off")?;
         db.pragma_update(None, "locking_mode", "exclusive")?;
+        Ok(db)
+    }
+
+    fn create_db(path: &Path) -> Result<Connection> {
+        let db = Self::open_db(path)?;
         db.execute(
             "\
 CREATE TABLE account  (
@@ -157,6 +186,56 @@ CREATE TABLE token_metadata (
     edition_nonce INTEGER(2) NULL,
     collection_verified INTEGER(1) NULL,
     collection_key BLOB(32) NULL
+);",
+            [],
+        )?;
+        db.execute(
+            "\
+CREATE TABLE stake_delegation (
+    stake_pubkey BLOB(32) NOT NULL PRIMARY KEY,
+    voter_pubkey BLOB(32) NOT NULL,
+    stake INTEGER(8) NOT NULL,
+    activation_epoch INTEGER(8) NOT NULL,
+    deactivation_epoch INTEGER(8) NOT NULL
+);",
+            [],
+        )?;
+        db.execute(
+            "\
+CREATE TABLE vote_account (
+    vote_pubkey BLOB(32) NOT NULL PRIMARY KEY,
+    node_pubkey BLOB(32) NULL,
+    stake INTEGER(8) NOT NULL,
+    lamports INTEGER(8) NOT NULL
+);",
+            [],
+        )?;
+        db.execute(
+            "\
+CREATE TABLE epoch_stake (
+    epoch INTEGER(8) NOT NULL PRIMARY KEY,
+    total_stake INTEGER(8) NOT NULL
+);",
+            [],
+        )?;
+        db.execute(
+            "\
+CREATE TABLE epoch_vote_account (
+    epoch INTEGER(8) NOT NULL,
+    vote_pubkey BLOB(32) NOT NULL,
+    node_pubkey BLOB(32) NULL,
+    stake INTEGER(8) NOT NULL,
+    PRIMARY KEY (epoch, vote_pubkey)
+);",
+            [],
+        )?;
+        db.execute(
+            "\
+CREATE TABLE epoch_authorized_voter (
+    epoch INTEGER(8) NOT NULL,
+    vote_pubkey BLOB(32) NOT NULL,
+    authorized_voter BLOB(32) NOT NULL,
+    PRIMARY KEY (epoch, vote_pubkey)
 );",
             [],
         )?;
@@ -169,13 +248,81 @@ CREATE TABLE token_metadata (
         Ok(())
     }
 
-    pub(crate) fn insert_all(mut self, iterator: AppendVecIterator) -> Result<IndexStats> {
+    /// Inserts stake delegations, vote accounts and epoch stakes from the manifest.
+    pub(crate) fn insert_stakes(&mut self, manifest: &SnapshotManifest) -> Result<()> {
+        let mut insert = self.db.prepare_cached(
+            "\
+INSERT OR REPLACE INTO stake_delegation (stake_pubkey, voter_pubkey, stake, activation_epoch, deactivation_epoch)
+    VALUES (?, ?, ?, ?, ?);",
+        )?;
+        for d in manifest.stake_delegations() {
+            insert.insert(params![
+                d.stake_pubkey.as_ref(),
+                d.voter_pubkey.as_ref(),
+                d.stake as i64,
+                d.activation_epoch as i64,
+                d.deactivation_epoch as i64,
+            ])?;
+        }
+        let mut insert = self.db.prepare_cached(
+            "\
+INSERT OR REPLACE INTO vote_account (vote_pubkey, node_pubkey, stake, lamports)
+    VALUES (?, ?, ?, ?);",
+        )?;
+        for v in manifest.vote_accounts() {
+            insert.insert(params![
+                v.vote_pubkey.as_ref(),
+                v.node_pubkey.map(|key| key.to_bytes()),
+                v.stake as i64,
+                v.lamports as i64,
+            ])?;
+        }
+        let mut insert_epoch = self.db.prepare_cached(
+            "\
+INSERT OR REPLACE INTO epoch_stake (epoch, total_stake)
+    VALUES (?, ?);",
+        )?;
+        let mut insert_vote = self.db.prepare_cached(
+            "\
+INSERT OR REPLACE INTO epoch_vote_account (epoch, vote_pubkey, node_pubkey, stake)
+    VALUES (?, ?, ?, ?);",
+        )?;
+        let mut insert_voter = self.db.prepare_cached(
+            "\
+INSERT OR REPLACE INTO epoch_authorized_voter (epoch, vote_pubkey, authorized_voter)
+    VALUES (?, ?, ?);",
+        )?;
+        for e in manifest.epoch_stakes() {
+            insert_epoch.insert(params![e.epoch as i64, e.total_stake as i64])?;
+            for v in &e.vote_accounts {
+                insert_vote.insert(params![
+                    e.epoch as i64,
+                    v.vote_pubkey.as_ref(),
+                    v.node_pubkey.map(|key| key.to_bytes()),
+                    v.stake as i64,
+                ])?;
+            }
+            for (vote_pubkey, authorized_voter) in &e.authorized_voters {
+                insert_voter.insert(params![
+                    e.epoch as i64,
+                    vote_pubkey.as_ref(),
+                    authorized_voter.as_ref(),
+                ])?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn insert_all<I>(mut self, accounts: I) -> Result<IndexStats>
+    where
+        I: Iterator<Item = solana_snapshot_etl::Result<LatestAccount>>,
+    {
         let mut worker = Worker {
             db: &self.db,
             progress: Arc::clone(&self.progress),
         };
-        for append_vec in iterator {
-            worker.on_append_vec(append_vec?)?;
+        for account in accounts {
+            worker.insert_account(&account?.access())?;
         }
         self.db.pragma_update(None, "query_only", true)?;
         let stats = IndexStats {
@@ -193,15 +340,6 @@ struct Worker<'a> {
     progress: Arc<Progress>,
 }
 
-impl<'a> AppendVecConsumer for Worker<'a> {
-    fn on_append_vec(&mut self, append_vec: AppendVec) -> GenericResult<()> {
-        for acc in append_vec_iter(Rc::new(append_vec)) {
-            self.insert_account(&acc.access().unwrap())?;
-        }
-        Ok(())
-    }
-}
-
 impl<'a> Worker<'a> {
     fn insert_account(&mut self, account: &StoredAccountMeta) -> Result<()> {
         self.insert_account_meta(account)?;
@@ -434,16 +572,28 @@ impl Drop for ProgressCounter {
     }
 }
 
-struct TempFileGuard {
+pub(crate) struct TempFileGuard {
     pub path: Option<PathBuf>,
+    keep: bool,
 }
 
 impl TempFileGuard {
-    fn new(path: PathBuf) -> Self {
-        Self { path: Some(path) }
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path: Some(path),
+            keep: false,
+        }
+    }
+
+    /// Creates a guard that leaves the file in place when dropped, so that it can be resumed.
+    pub(crate) fn new_kept(path: PathBuf) -> Self {
+        Self {
+            path: Some(path),
+            keep: true,
+        }
     }
 
-    fn promote<P: AsRef<Path>>(&mut self, new_name: P) -> std::io::Result<()> {
+    pub(crate) fn promote<P: AsRef<Path>>(&mut self, new_name: P) -> std::io::Result<()> {
         std::fs::rename(
             self.path.take().expect("cannot promote non-existent file"),
             new_name,
@@ -453,7 +603,7 @@ impl TempFileGuard {
 
 impl Drop for TempFileGuard {
     fn drop(&mut self) {
-        if let Some(path) = &self.path {
+        if let Some(path) = self.path.as_ref().filter(|_| !self.keep) {
             if let Err(e) = std::fs::remove_file(path) {
                 error!("Failed to remove temp DB: {}", e);
             }
@@ -0,0 +1,109 @@
+use serde::Serialize;
+use solana_snapshot_etl::manifest::SnapshotManifest;
+use std::fs::OpenOptions;
+use std::path::Path;
+
+pub(crate) type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Serialize)]
+struct StakeDelegationRecord {
+    stake_pubkey: String,
+    voter_pubkey: String,
+    stake: u64,
+    activation_epoch: u64,
+    deactivation_epoch: u64,
+}
+
+#[derive(Serialize)]
+struct VoteAccountRecord {
+    vote_pubkey: String,
+    node_pubkey: Option<String>,
+    stake: u64,
+    lamports: u64,
+}
+
+#[derive(Serialize)]
+struct EpochStakeRecord {
+    epoch: u64,
+    total_stake: u64,
+}
+
+#[derive(Serialize)]
+struct EpochVoteAccountRecord {
+    epoch: u64,
+    vote_pubkey: String,
+    node_pubkey: Option<String>,
+    stake: u64,
+}
+
+#[derive(Serialize)]
+struct EpochAuthorizedVoterRecord {
+    epoch: u64,
+    vote_pubkey: String,
+    authorized_voter: String,
+}
+
+/// Writes stakes from the snapshot manifest as one CSV file per table.
+pub(crate) fn dump_stakes_csv(manifest: &SnapshotManifest, dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let open = |name: &str| -> Result<csv::Writer<std::fs::File>> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dir.join(name))?;
+        Ok(csv::Writer::from_writer(file))
+    };
+
+    let mut writer = open("stake_delegation.csv")?;
+    for d in manifest.stake_delegations() {
+        writer.serialize(StakeDelegationRecord {
+            stake_pubkey: d.stake_pubkey.to_string(),
+            voter_pubkey: d.voter_pubkey.to_string(),
+            stake: d.stake,
+            activation_epoch: d.activation_epoch,
+            deactivation_epoch: d.deactivation_epoch,
+        })?;
+    }
+    writer.flush()?;
+
+    let mut writer = open("vote_account.csv")?;
+    for v in manifest.vote_accounts() {
+        writer.serialize(VoteAccountRecord {
+            vote_pubkey: v.vote_pubkey.to_string(),
+            node_pubkey: v.node_pubkey.map(|key| key.to_string()),
+            stake: v.stake,
+            lamports: v.lamports,
+        })?;
+    }
+    writer.flush()?;
+
+    let epoch_stakes = manifest.epoch_stakes();
+    let mut epoch_writer = open("epoch_stake.csv")?;
+    let mut vote_writer = open("epoch_vote_account.csv")?;
+    let mut voter_writer = open("epoch_authorized_voter.csv")?;
+    for e in &epoch_stakes {
+        epoch_writer.serialize(EpochStakeRecord {
+            epoch: e.epoch,
+            total_stake: e.total_stake,
+        })?;
+        for v in &e.vote_accounts {
+            vote_writer.serialize(EpochVoteAccountRecord {
+                epoch: e.epoch,
+                vote_pubkey: v.vote_pubkey.to_string(),
+                node_pubkey: v.node_pubkey.map(|key| key.to_string()),
+                stake: v.stake,
+            })?;
+        }
+        for (vote_pubkey, authorized_voter) in &e.authorized_voters {
+            voter_writer.serialize(EpochAuthorizedVoterRecord {
+                epoch: e.epoch,
+                vote_pubkey: vote_pubkey.to_string(),
+                authorized_voter: authorized_voter.to_string(),
+            })?;
+        }
+    }
+    epoch_writer.flush()?;
+    vote_writer.flush()?;
+    voter_writer.flush()?;
+    Ok(())
+}
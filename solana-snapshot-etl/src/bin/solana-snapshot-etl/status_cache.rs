@@ -0,0 +1,64 @@
+use serde::Serialize;
+use solana_snapshot_etl::status_cache::SlotStatuses;
+use std::io::Write;
+
+/// Writes the transaction statuses of the status cache as CSV.
+pub(crate) struct StatusCacheDumper {
+    writer: csv::Writer<Box<dyn Write>>,
+}
+
+#[derive(Serialize)]
+struct Record {
+    slot: u64,
+    is_root: bool,
+    blockhash: String,
+    key_index: usize,
+    key_slice: String,
+    status: String,
+}
+
+#[derive(Default)]
+pub(crate) struct DumperStats {
+    pub(crate) slots_total: u64,
+    pub(crate) statuses_total: u64,
+}
+
+impl StatusCacheDumper {
+    pub(crate) fn new(writer: Box<dyn Write>) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(writer),
+        }
+    }
+
+    pub(crate) fn dump_status_cache(
+        &mut self,
+        slots: &[SlotStatuses],
+    ) -> Result<DumperStats, Box<dyn std::error::Error>> {
+        let mut stats = DumperStats::default();
+        for slot in slots {
+            stats.slots_total += 1;
+            for blockhash in &slot.blockhashes {
+                for status in &blockhash.statuses {
+                    self.writer.serialize(Record {
+                        slot: slot.slot,
+                        is_root: slot.is_root,
+                        blockhash: blockhash.blockhash.to_string(),
+                        key_index: blockhash.key_index,
+                        key_slice: status
+                            .key_slice
+                            .iter()
+                            .map(|b| format!("{:02x}", b))
+                            .collect(),
+                        status: match &status.result {
+                            Ok(()) => "ok".to_string(),
+                            Err(err) => err.to_string(),
+                        },
+                    })?;
+                    stats.statuses_total += 1;
+                }
+            }
+        }
+        self.writer.flush()?;
+        Ok(stats)
+    }
+}
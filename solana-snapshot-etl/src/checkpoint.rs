@@ -0,0 +1,378 @@
+use crate::dedup::{AccountDeduplicator, LatestAccount, LatestAccounts};
+use crate::filter::FilteredAccount;
+use crate::manifest::SnapshotManifest;
+use crate::solana::deserialize_from;
+use crate::{Result, SnapshotError};
+use log::info;
+use serde::{Deserialize, Serialize};
+use solana_sdk::clock::Slot;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+/// Number of accounts written to a sink between checkpoint saves.
+const SAVE_INTERVAL: u64 = 1 << 16;
+
+/// Progress of a run over a snapshot, saved to a file so that an interrupted run can be resumed.
+///
+/// A run first deduplicates accounts, spilling sorted runs next to the checkpoint file,
+/// then writes the latest accounts to a sink in pubkey order.
+/// The checkpoint records which AppendVecs have been fully committed to spilled runs,
+/// and the last account the sink has committed.
+/// It can only be resumed by a run with the same options, such as the account filter,
+/// which the caller fingerprints into a hash.
+pub struct Checkpoint {
+    path: PathBuf,
+    state: CheckpointState,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CheckpointState {
+    slot: Slot,
+    accounts_hash: Hash,
+    /// Fingerprint of the options the run was started with.
+    options: Hash,
+    append_vecs: HashSet<(Slot, u64)>,
+    runs: Vec<(PathBuf, usize)>,
+    dedup_done: bool,
+    last_pubkey: Option<Pubkey>,
+}
+
+impl Checkpoint {
+    /// Starts a new checkpoint for the snapshot with the given manifest and run options.
+    pub fn create(path: &Path, manifest: &SnapshotManifest, options: Hash) -> Result<Self> {
+        let checkpoint = Self {
+            path: path.to_path_buf(),
+            state: CheckpointState {
+                slot: manifest.slot(),
+                accounts_hash: *manifest.accounts_hash(),
+                options,
+                append_vecs: HashSet::new(),
+                runs: Vec::new(),
+                dedup_done: false,
+                last_pubkey: None,
+            },
+        };
+        checkpoint.save()?;
+        Ok(checkpoint)
+    }
+
+    /// Loads a checkpoint, which must have been created for the snapshot with the given manifest
+    /// and the same run options.
+    pub fn open(path: &Path, manifest: &SnapshotManifest, options: Hash) -> Result<Self> {
+        let state: CheckpointState = deserialize_from(BufReader::new(File::open(path)?))?;
+        if state.slot != manifest.slot() || state.accounts_hash != *manifest.accounts_hash() {
+            return Err(SnapshotError::CheckpointMismatch(format!(
+                "recorded for snapshot at slot {}, not for snapshot at slot {}",
+                state.slot,
+                manifest.slot()
+            )));
+        }
+        if state.options != options {
+            return Err(SnapshotError::CheckpointMismatch(
+                "recorded with other account filters or options".to_owned(),
+            ));
+        }
+        info!(
+            "Resuming from checkpoint with {} committed AppendVecs",
+            state.append_vecs.len()
+        );
+        Ok(Self {
+            path: path.to_path_buf(),
+            state,
+        })
+    }
+
+    /// Returns the directory holding the spilled runs of the deduplicator.
+    pub fn run_dir(&self) -> PathBuf {
+        let mut dir = OsString::from(self.path.as_os_str());
+        dir.push(".runs");
+        PathBuf::from(dir)
+    }
+
+    /// Returns the slot and id of each AppendVec committed to spilled runs.
+    pub fn append_vecs(&self) -> &HashSet<(Slot, u64)> {
+        &self.state.append_vecs
+    }
+
+    /// Returns whether all AppendVecs have been committed to spilled runs.
+    pub fn is_dedup_done(&self) -> bool {
+        self.state.dedup_done
+    }
+
+    /// Returns the last account the sink committed.
+    pub fn last_pubkey(&self) -> Option<Pubkey> {
+        self.state.last_pubkey
+    }
+
+    /// Forgets the accounts committed to the sink, e.g. when its output was lost.
+    pub fn reset_sink(&mut self) -> Result<()> {
+        self.state.last_pubkey = None;
+        self.save()
+    }
+
+    /// Writes the checkpoint to a temporary file and moves it into place.
+    pub fn save(&self) -> Result<()> {
+        let mut tmp_path = OsString::from(self.path.as_os_str());
+        tmp_path.push(".tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        bincode::serialize_into(&mut writer, &self.state)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Deletes the checkpoint and spilled runs once the run has completed.
+    pub fn remove(self) -> Result<()> {
+        let run_dir = self.run_dir();
+        if run_dir.exists() {
+            std::fs::remove_dir_all(run_dir)?;
+        }
+        std::fs::remove_file(&self.path)?;
+        Ok(())
+    }
+
+    /// Skips the accounts that the sink committed in an earlier run,
+    /// and records the progress of the sink while it consumes the rest.
+    ///
+    /// An account counts as committed once the sink asks for the next one.
+    pub fn track_sink<I>(&mut self, accounts: I) -> CheckpointedAccounts<'_, I>
+    where
+        I: Iterator<Item = Result<LatestAccount>>,
+    {
+        if let Some(last_pubkey) = self.state.last_pubkey {
+            info!("Skipping accounts up to {}", last_pubkey);
+        }
+        CheckpointedAccounts {
+            checkpoint: self,
+            accounts,
+            pending: None,
+            since_save: 0,
+        }
+    }
+}
+
+/// Deduplicates accounts while committing AppendVecs to a checkpoint.
+///
+/// AppendVecs are committed whenever the deduplicator spills a run,
+/// so that a resumed run only re-reads the AppendVecs whose accounts were still in memory.
+/// Accounts must be inserted one AppendVec at a time.
+pub struct CheckpointedDeduplicator<'a> {
+    dedup: AccountDeduplicator,
+    checkpoint: &'a mut Checkpoint,
+    current: Option<(Slot, u64)>,
+    pending: Vec<(Slot, u64)>,
+}
+
+impl<'a> CheckpointedDeduplicator<'a> {
+    pub fn new(checkpoint: &'a mut Checkpoint, mem_budget: usize) -> Result<Self> {
+        let dedup =
+            AccountDeduplicator::resume(&checkpoint.run_dir(), mem_budget, &checkpoint.state.runs)?;
+        Ok(Self {
+            dedup,
+            checkpoint,
+            current: None,
+            pending: Vec::new(),
+        })
+    }
+
+    /// See `AccountDeduplicator::keep_deleted`.
+    pub fn keep_deleted(&mut self, keep_deleted: bool) {
+        self.dedup.keep_deleted(keep_deleted);
+    }
+
+    /// Returns whether an earlier run committed all AppendVecs,
+    /// in which case the snapshot does not need to be read again.
+    pub fn is_done(&self) -> bool {
+        self.checkpoint.is_dedup_done()
+    }
+
+    pub fn insert_filtered(&mut self, slot: Slot, id: u64, account: FilteredAccount) -> Result<()> {
+        if self.current != Some((slot, id)) {
+            self.pending.extend(self.current.replace((slot, id)));
+        }
+        let runs = self.dedup.run_count();
+        self.dedup.insert_filtered(slot, id, account)?;
+        if self.dedup.run_count() != runs {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let state = &mut self.checkpoint.state;
+        state.append_vecs.extend(self.pending.drain(..));
+        state.runs = self.dedup.runs();
+        self.checkpoint.save()
+    }
+
+    /// Commits all remaining accounts and returns the latest version of each account.
+    pub fn finish(mut self) -> Result<LatestAccounts> {
+        if !self.checkpoint.state.dedup_done {
+            self.dedup.spill()?;
+            let state = &mut self.checkpoint.state;
+            state.append_vecs.clear();
+            state.runs = self.dedup.runs();
+            state.dedup_done = true;
+            self.checkpoint.save()?;
+        }
+        self.dedup.finish()
+    }
+}
+
+/// Iterator returned by `Checkpoint::track_sink`.
+pub struct CheckpointedAccounts<'a, I> {
+    checkpoint: &'a mut Checkpoint,
+    accounts: I,
+    pending: Option<Pubkey>,
+    since_save: u64,
+}
+
+impl<'a, I> CheckpointedAccounts<'a, I>
+where
+    I: Iterator<Item = Result<LatestAccount>>,
+{
+    fn next_account(&mut self) -> Result<Option<LatestAccount>> {
+        if let Some(pubkey) = self.pending.take() {
+            self.checkpoint.state.last_pubkey = Some(pubkey);
+            self.since_save += 1;
+        }
+        loop {
+            let account = match self.accounts.next() {
+                Some(account) => account?,
+                None => {
+                    self.checkpoint.save()?;
+                    return Ok(None);
+                }
+            };
+            if self.since_save >= SAVE_INTERVAL {
+                self.checkpoint.save()?;
+                self.since_save = 0;
+            }
+            if self
+                .checkpoint
+                .state
+                .last_pubkey
+                .is_some_and(|last| account.meta.pubkey <= last)
+            {
+                continue;
+            }
+            self.pending = Some(account.meta.pubkey);
+            return Ok(Some(account));
+        }
+    }
+}
+
+impl<'a, I> Iterator for CheckpointedAccounts<'a, I>
+where
+    I: Iterator<Item = Result<LatestAccount>>,
+{
+    type Item = Result<LatestAccount>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_account().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{manifest, pubkey, TestAccount};
+
+    /// AppendVecs of a snapshot, in the order they are read.
+    fn append_vecs() -> Vec<(Slot, u64, Vec<TestAccount>)> {
+        vec![
+            (
+                1,
+                0,
+                vec![
+                    TestAccount::new(pubkey(1), 1, 10, &[1]),
+                    TestAccount::new(pubkey(2), 2, 10, &[2]),
+                ],
+            ),
+            (
+                2,
+                1,
+                vec![
+                    TestAccount::new(pubkey(1), 3, 20, &[3]),
+                    TestAccount::new(pubkey(3), 4, 10, &[4]),
+                ],
+            ),
+            (
+                3,
+                2,
+                vec![
+                    TestAccount::new(pubkey(2), 5, 0, &[]),
+                    TestAccount::new(pubkey(4), 6, 10, &[5]),
+                ],
+            ),
+        ]
+    }
+
+    /// Deduplicates the AppendVecs the checkpoint has not committed yet.
+    ///
+    /// Returns `None` if the run was stopped once it committed its first AppendVec.
+    fn dedup(checkpoint: &mut Checkpoint, stop: bool) -> Option<Vec<(Pubkey, Slot, u64)>> {
+        let committed = checkpoint.append_vecs().clone();
+        // Every insert spills a run.
+        let mut dedup = CheckpointedDeduplicator::new(checkpoint, 0).unwrap();
+        for (slot, id, accounts) in append_vecs() {
+            if committed.contains(&(slot, id)) {
+                continue;
+            }
+            for account in &accounts {
+                let hash = account.hash(slot);
+                dedup
+                    .insert_filtered(slot, id, FilteredAccount::Matched(account.access(&hash)))
+                    .unwrap();
+                if stop && !dedup.checkpoint.append_vecs().is_empty() {
+                    return None;
+                }
+            }
+        }
+        let accounts = dedup.finish().unwrap();
+        Some(
+            accounts
+                .map(|account| {
+                    let account = account.unwrap();
+                    (
+                        account.meta.pubkey,
+                        account.slot,
+                        account.meta.write_version,
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn resumes_after_spilled_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = manifest(10);
+        let options = Hash::default();
+
+        let path = dir.path().join("uninterrupted");
+        let mut checkpoint = Checkpoint::create(&path, &manifest, options).unwrap();
+        let expected = dedup(&mut checkpoint, false).unwrap();
+        assert_eq!(
+            expected,
+            vec![(pubkey(1), 2, 3), (pubkey(3), 2, 4), (pubkey(4), 3, 6)]
+        );
+
+        let path = dir.path().join("interrupted");
+        let mut checkpoint = Checkpoint::create(&path, &manifest, options).unwrap();
+        assert_eq!(dedup(&mut checkpoint, true), None);
+        drop(checkpoint);
+
+        let mut checkpoint = Checkpoint::open(&path, &manifest, options).unwrap();
+        assert_eq!(checkpoint.append_vecs(), &HashSet::from([(1, 0)]));
+        assert!(!checkpoint.state.runs.is_empty());
+        assert_eq!(dedup(&mut checkpoint, false).unwrap(), expected);
+        assert!(checkpoint.is_dedup_done());
+    }
+}
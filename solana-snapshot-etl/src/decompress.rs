@@ -0,0 +1,130 @@
+use log::info;
+use solana_runtime::snapshot_utils::ArchiveFormat;
+use std::io::{BufReader, Chain, Cursor, Read};
+
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+const LZ4_MAGIC: &[u8] = &[0x04, 0x22, 0x4d, 0x18];
+const TAR_MAGIC: &[u8] = b"ustar";
+const TAR_MAGIC_OFFSET: usize = 257;
+const TAR_HEADER_SIZE: usize = 512;
+
+/// Detects the archive format from the first bytes of a stream.
+pub fn detect_archive_format(header: &[u8]) -> Option<ArchiveFormat> {
+    if header.starts_with(ZSTD_MAGIC) {
+        Some(ArchiveFormat::TarZstd)
+    } else if header.starts_with(GZIP_MAGIC) {
+        Some(ArchiveFormat::TarGzip)
+    } else if header.starts_with(BZIP2_MAGIC) {
+        Some(ArchiveFormat::TarBzip2)
+    } else if header.starts_with(LZ4_MAGIC) {
+        Some(ArchiveFormat::TarLz4)
+    } else if header
+        .get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len())
+        .map(|magic| magic == TAR_MAGIC)
+        .unwrap_or(false)
+    {
+        Some(ArchiveFormat::Tar)
+    } else {
+        None
+    }
+}
+
+/// Source stream with its first bytes read ahead for format detection.
+pub type PeekedReader<Source> = BufReader<Chain<Cursor<Vec<u8>>, Source>>;
+
+/// Decompresses a snapshot archive stream into a tar stream.
+pub enum ArchiveDecoder<Source: Read> {
+    Zstd(zstd::Decoder<'static, PeekedReader<Source>>),
+    Gzip(flate2::bufread::GzDecoder<PeekedReader<Source>>),
+    Bzip2(bzip2::bufread::BzDecoder<PeekedReader<Source>>),
+    Lz4(lz4::Decoder<PeekedReader<Source>>),
+    Tar(PeekedReader<Source>),
+    #[cfg(feature = "parallel")]
+    Pipelined(crate::pipeline::PipelinedDecoder<Source>),
+}
+
+impl<Source: Read> ArchiveDecoder<Source> {
+    /// Sniffs the archive format and sets up the matching decoder.
+    ///
+    /// Uncompressed streams are only accepted as tar archives if they carry the ustar magic.
+    pub fn new(mut source: Source) -> std::io::Result<Self> {
+        let (header, format) = Self::peek_format(&mut source)?;
+        let reader = BufReader::new(Cursor::new(header).chain(source));
+        Self::with_format(reader, format)
+    }
+
+    /// Like `new`, but decompresses on background threads while the source is read on this one.
+    ///
+    /// See `PipelinedDecoder`.
+    #[cfg(feature = "parallel")]
+    pub fn pipelined(mut source: Source, num_threads: usize) -> std::io::Result<Self> {
+        let (header, format) = Self::peek_format(&mut source)?;
+        Ok(Self::Pipelined(crate::pipeline::PipelinedDecoder::new(
+            source,
+            header,
+            format,
+            num_threads,
+        )))
+    }
+
+    fn peek_format(source: &mut Source) -> std::io::Result<(Vec<u8>, ArchiveFormat)> {
+        let mut header = Vec::with_capacity(TAR_HEADER_SIZE);
+        source
+            .take(TAR_HEADER_SIZE as u64)
+            .read_to_end(&mut header)?;
+        let format = detect_archive_format(&header).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "unknown archive format")
+        })?;
+        info!("Detected archive format: {}", format);
+        Ok((header, format))
+    }
+
+    pub(crate) fn with_format(
+        reader: PeekedReader<Source>,
+        format: ArchiveFormat,
+    ) -> std::io::Result<Self> {
+        Ok(match format {
+            ArchiveFormat::TarZstd => Self::Zstd(zstd::Decoder::with_buffer(reader)?),
+            ArchiveFormat::TarGzip => Self::Gzip(flate2::bufread::GzDecoder::new(reader)),
+            ArchiveFormat::TarBzip2 => Self::Bzip2(bzip2::bufread::BzDecoder::new(reader)),
+            ArchiveFormat::TarLz4 => Self::Lz4(lz4::Decoder::new(reader)?),
+            ArchiveFormat::Tar => Self::Tar(reader),
+        })
+    }
+}
+
+impl<Source: Read> Read for ArchiveDecoder<Source> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Zstd(rd) => rd.read(buf),
+            Self::Gzip(rd) => rd.read(buf),
+            Self::Bzip2(rd) => rd.read(buf),
+            Self::Lz4(rd) => rd.read(buf),
+            Self::Tar(rd) => rd.read(buf),
+            #[cfg(feature = "parallel")]
+            Self::Pipelined(rd) => rd.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_unknown_archive_format() {
+        let mut tar = vec![0u8; TAR_HEADER_SIZE];
+        tar[TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()].copy_from_slice(TAR_MAGIC);
+        assert_eq!(detect_archive_format(&tar), Some(ArchiveFormat::Tar));
+        assert!(ArchiveDecoder::new(&tar[..]).is_ok());
+
+        let err = ArchiveDecoder::new(&[0u8; TAR_HEADER_SIZE][..])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "unknown archive format");
+        assert!(ArchiveDecoder::new(&b"not an archive"[..]).is_err());
+    }
+}
@@ -0,0 +1,495 @@
+use crate::append_vec::{AccountMeta, AppendVec, StoredAccountMeta, StoredMeta};
+use crate::filter::{AccountFilter, FilteredAccount};
+use crate::solana::deserialize_from;
+use crate::{AppendVecIterator, CorruptionPolicy, Result, SnapshotExtractor};
+use log::info;
+use serde::{Deserialize, Serialize};
+use solana_sdk::clock::Slot;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// Owned copy of a stored account, tagged with the AppendVec it was read from.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LatestAccount {
+    pub slot: Slot,
+    pub id: u64,
+    pub offset: usize,
+    pub stored_size: usize,
+    pub meta: StoredMeta,
+    pub account_meta: AccountMeta,
+    pub hash: Hash,
+    pub data: Vec<u8>,
+    /// Version rejected by an account filter, kept to supersede older versions.
+    pub rejected: bool,
+}
+
+impl LatestAccount {
+    pub fn new(slot: Slot, id: u64, account: &StoredAccountMeta) -> Self {
+        Self {
+            slot,
+            id,
+            offset: account.offset,
+            stored_size: account.stored_size,
+            meta: account.meta.clone(),
+            account_meta: account.account_meta.clone(),
+            hash: *account.hash,
+            data: account.data.to_vec(),
+            rejected: false,
+        }
+    }
+
+    /// Creates a placeholder for an account version that did not match a filter.
+    pub fn rejected(slot: Slot, id: u64, meta: &StoredMeta) -> Self {
+        Self {
+            slot,
+            id,
+            offset: 0,
+            stored_size: 0,
+            meta: meta.clone(),
+            account_meta: AccountMeta::default(),
+            hash: Hash::default(),
+            data: Vec::new(),
+            rejected: true,
+        }
+    }
+
+    pub fn access(&self) -> StoredAccountMeta<'_> {
+        StoredAccountMeta {
+            meta: &self.meta,
+            account_meta: &self.account_meta,
+            data: &self.data,
+            offset: self.offset,
+            stored_size: self.stored_size,
+            hash: &self.hash,
+        }
+    }
+
+    /// Accounts with a higher version supersede those with a lower one.
+    pub fn version(&self) -> (Slot, u64) {
+        (self.slot, self.meta.write_version)
+    }
+
+    fn mem_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.data.len()
+    }
+}
+
+/// Keeps only the latest version (highest slot and write version) of each account.
+///
+/// Accounts whose latest version has zero lamports were deleted, and are left out
+/// unless `keep_deleted` is set.
+/// Accounts are buffered in memory until `mem_budget` bytes are used.
+/// Then, the buffer is sorted by pubkey and spilled into a run file,
+/// and the run files are merged once all accounts have been inserted.
+pub struct AccountDeduplicator {
+    dir: PathBuf,
+    temp_dir: Option<TempDir>,
+    mem_budget: usize,
+    mem_used: usize,
+    keep_deleted: bool,
+    accounts: HashMap<Pubkey, LatestAccount>,
+    runs: Vec<SpillRun>,
+}
+
+struct SpillRun {
+    path: PathBuf,
+    len: usize,
+}
+
+impl AccountDeduplicator {
+    pub fn new(spill_dir: &Path, mem_budget: usize) -> Result<Self> {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("account-dedup")
+            .tempdir_in(spill_dir)?;
+        Ok(Self {
+            dir: temp_dir.path().to_path_buf(),
+            temp_dir: Some(temp_dir),
+            mem_budget,
+            mem_used: 0,
+            keep_deleted: false,
+            accounts: HashMap::new(),
+            runs: Vec::new(),
+        })
+    }
+
+    /// Continues from run files spilled into `dir` by an earlier deduplicator.
+    ///
+    /// Run files are kept once the deduplicator is dropped.
+    pub fn resume(dir: &Path, mem_budget: usize, runs: &[(PathBuf, usize)]) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            temp_dir: None,
+            mem_budget,
+            mem_used: 0,
+            keep_deleted: false,
+            accounts: HashMap::new(),
+            runs: runs
+                .iter()
+                .map(|(path, len)| SpillRun {
+                    path: path.clone(),
+                    len: *len,
+                })
+                .collect(),
+        })
+    }
+
+    /// Returns zero-lamport accounts as well, e.g. when a filter asks for them.
+    pub fn keep_deleted(&mut self, keep_deleted: bool) {
+        self.keep_deleted = keep_deleted;
+    }
+
+    pub fn run_count(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Returns the path and number of accounts of each spilled run file.
+    pub fn runs(&self) -> Vec<(PathBuf, usize)> {
+        self.runs
+            .iter()
+            .map(|run| (run.path.clone(), run.len))
+            .collect()
+    }
+
+    pub fn insert_append_vec(&mut self, append_vec: &AppendVec) -> Result<()> {
+        let mut offset = 0usize;
+        while let Some((account, next_offset)) = append_vec.try_get_account(offset)? {
+            self.insert(append_vec.slot(), append_vec.id(), &account)?;
+            offset = next_offset;
+        }
+        Ok(())
+    }
+
+    pub fn insert(&mut self, slot: Slot, id: u64, account: &StoredAccountMeta) -> Result<()> {
+        if self.has_newer(account.meta, slot) {
+            return Ok(());
+        }
+        if account.account_meta.lamports == 0 && !self.keep_deleted {
+            return self.push(LatestAccount::rejected(slot, id, account.meta));
+        }
+        self.push(LatestAccount::new(slot, id, account))
+    }
+
+    /// Inserts an account seen by a filtered walk.
+    ///
+    /// Rejected versions are tracked but never returned,
+    /// so that an older version matching the filter is not mistaken for the latest.
+    pub fn insert_filtered(&mut self, slot: Slot, id: u64, account: FilteredAccount) -> Result<()> {
+        match account {
+            FilteredAccount::Matched(account) => self.insert(slot, id, &account),
+            FilteredAccount::Rejected(meta) => {
+                if self.has_newer(meta, slot) {
+                    return Ok(());
+                }
+                self.push(LatestAccount::rejected(slot, id, meta))
+            }
+        }
+    }
+
+    fn has_newer(&self, meta: &StoredMeta, slot: Slot) -> bool {
+        self.accounts
+            .get(&meta.pubkey)
+            .is_some_and(|existing| existing.version() >= (slot, meta.write_version))
+    }
+
+    fn push(&mut self, account: LatestAccount) -> Result<()> {
+        self.mem_used += account.mem_size();
+        if let Some(replaced) = self.accounts.insert(account.meta.pubkey, account) {
+            self.mem_used -= replaced.mem_size();
+        }
+        if self.mem_used > self.mem_budget {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Sorts buffered accounts by pubkey and writes them into a new run file.
+    pub fn spill(&mut self) -> Result<()> {
+        if self.accounts.is_empty() {
+            return Ok(());
+        }
+        let mut accounts = self.accounts.drain().map(|(_, v)| v).collect::<Vec<_>>();
+        accounts.sort_unstable_by_key(|a| a.meta.pubkey);
+        let path = self.dir.join(format!("run-{}", self.runs.len()));
+        info!(
+            "Spilling {} accounts ({} bytes) to {:?}",
+            accounts.len(),
+            self.mem_used,
+            &path
+        );
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for account in &accounts {
+            bincode::serialize_into(&mut writer, account)?;
+        }
+        // Checkpoints record runs once spilled, so they must be durable by then.
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        self.runs.push(SpillRun {
+            path,
+            len: accounts.len(),
+        });
+        self.mem_used = 0;
+        Ok(())
+    }
+
+    /// Returns the latest version of each inserted account, ordered by pubkey.
+    pub fn finish(mut self) -> Result<LatestAccounts> {
+        if self.runs.is_empty() {
+            let mut accounts = self
+                .accounts
+                .into_values()
+                .filter(|account| !account.rejected)
+                .collect::<Vec<_>>();
+            accounts.sort_unstable_by_key(|a| a.meta.pubkey);
+            return Ok(LatestAccounts::Memory(accounts.into_iter()));
+        }
+        self.spill()?;
+        let mut merge = RunMerge {
+            runs: Vec::with_capacity(self.runs.len()),
+            heap: BinaryHeap::with_capacity(self.runs.len()),
+            _dir: self.temp_dir,
+        };
+        for run in self.runs {
+            let reader = RunReader {
+                rd: BufReader::new(File::open(&run.path)?),
+                remaining: run.len,
+                head: None,
+            };
+            merge.runs.push(reader);
+            merge.refill(merge.runs.len() - 1)?;
+        }
+        Ok(LatestAccounts::Merge(merge))
+    }
+}
+
+/// Reads all AppendVecs and returns the latest version of each account.
+pub fn dedup_append_vecs(
+    iterator: AppendVecIterator<'_>,
+    spill_dir: &Path,
+    mem_budget: usize,
+) -> Result<LatestAccounts> {
+    let mut dedup = AccountDeduplicator::new(spill_dir, mem_budget)?;
+    for append_vec in iterator {
+        dedup.insert_append_vec(&append_vec?)?;
+    }
+    dedup.finish()
+}
+
+/// Streams all accounts of a snapshot and returns the latest version of each account
+/// for accounts whose latest version matches `filter`.
+///
+/// Deleted accounts are only returned if the filter selects zero-lamport accounts.
+pub fn dedup_accounts<E: SnapshotExtractor>(
+    extractor: &mut E,
+    policy: CorruptionPolicy,
+    filter: &AccountFilter,
+    spill_dir: &Path,
+    mem_budget: usize,
+) -> Result<LatestAccounts> {
+    let mut dedup = AccountDeduplicator::new(spill_dir, mem_budget)?;
+    dedup.keep_deleted(filter.selects_zero_lamport());
+    extractor.for_each_filtered_account(policy, filter, &mut |slot, id, account| {
+        dedup.insert_filtered(slot, id, account)
+    })?;
+    dedup.finish()
+}
+
+/// Iterator over deduplicated accounts returned by `AccountDeduplicator::finish`.
+pub enum LatestAccounts {
+    Memory(std::vec::IntoIter<LatestAccount>),
+    Merge(RunMerge),
+}
+
+impl Iterator for LatestAccounts {
+    type Item = Result<LatestAccount>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            LatestAccounts::Memory(iter) => iter.next().map(Ok),
+            LatestAccounts::Merge(merge) => merge.next(),
+        }
+    }
+}
+
+/// K-way merge over sorted run files.
+pub struct RunMerge {
+    runs: Vec<RunReader>,
+    heap: BinaryHeap<Reverse<(Pubkey, usize)>>,
+    _dir: Option<TempDir>,
+}
+
+struct RunReader {
+    rd: BufReader<File>,
+    remaining: usize,
+    head: Option<LatestAccount>,
+}
+
+impl RunMerge {
+    /// Reads the next account of a run into its head and schedules it for merging.
+    fn refill(&mut self, idx: usize) -> Result<()> {
+        let run = &mut self.runs[idx];
+        if run.remaining == 0 {
+            return Ok(());
+        }
+        let account: LatestAccount = deserialize_from(&mut run.rd)?;
+        run.remaining -= 1;
+        self.heap.push(Reverse((account.meta.pubkey, idx)));
+        run.head = Some(account);
+        Ok(())
+    }
+
+    fn take(&mut self, idx: usize) -> Result<LatestAccount> {
+        let account = self.runs[idx].head.take().expect("run has no head");
+        self.refill(idx)?;
+        Ok(account)
+    }
+
+    fn next_latest(&mut self, pubkey: Pubkey, idx: usize) -> Result<LatestAccount> {
+        let mut latest = self.take(idx)?;
+        while let Some(Reverse((next_pubkey, next_idx))) = self.heap.peek().copied() {
+            if next_pubkey != pubkey {
+                break;
+            }
+            self.heap.pop();
+            let account = self.take(next_idx)?;
+            if account.version() > latest.version() {
+                latest = account;
+            }
+        }
+        Ok(latest)
+    }
+}
+
+impl Iterator for RunMerge {
+    type Item = Result<LatestAccount>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Reverse((pubkey, idx)) = self.heap.pop()?;
+            match self.next_latest(pubkey, idx) {
+                Ok(latest) if latest.rejected => continue,
+                result => return Some(result),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{pubkey, TestAccount};
+
+    fn insert(dedup: &mut AccountDeduplicator, slot: Slot, account: &TestAccount) {
+        let hash = account.hash(slot);
+        dedup.insert(slot, 0, &account.access(&hash)).unwrap();
+    }
+
+    fn versions(accounts: LatestAccounts) -> Vec<(Pubkey, Slot, u64)> {
+        accounts
+            .map(|account| {
+                let account = account.unwrap();
+                (
+                    account.meta.pubkey,
+                    account.slot,
+                    account.meta.write_version,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merges_latest_versions_across_runs() {
+        let spill_dir = tempfile::tempdir().unwrap();
+        // Every insert spills a run.
+        let mut dedup = AccountDeduplicator::new(spill_dir.path(), 0).unwrap();
+        insert(&mut dedup, 5, &TestAccount::new(pubkey(3), 1, 10, &[1]));
+        insert(&mut dedup, 3, &TestAccount::new(pubkey(1), 1, 10, &[2]));
+        insert(&mut dedup, 7, &TestAccount::new(pubkey(3), 0, 10, &[3]));
+        insert(&mut dedup, 2, &TestAccount::new(pubkey(1), 9, 10, &[4]));
+        insert(&mut dedup, 4, &TestAccount::new(pubkey(2), 4, 10, &[5]));
+        insert(&mut dedup, 7, &TestAccount::new(pubkey(3), 5, 10, &[6]));
+        insert(&mut dedup, 7, &TestAccount::new(pubkey(3), 2, 10, &[7]));
+        assert_eq!(dedup.run_count(), 7);
+
+        let accounts = dedup.finish().unwrap().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(
+            accounts
+                .iter()
+                .map(|account| (account.meta.pubkey, account.version(), account.data[0]))
+                .collect::<Vec<_>>(),
+            vec![
+                (pubkey(1), (3, 1), 2),
+                (pubkey(2), (4, 4), 5),
+                (pubkey(3), (7, 5), 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejected_versions_shadow_older_matches() {
+        let spill_dir = tempfile::tempdir().unwrap();
+        let mut dedup = AccountDeduplicator::new(spill_dir.path(), 0).unwrap();
+        let older = TestAccount::new(pubkey(1), 1, 10, &[1]);
+        let newer = TestAccount::new(pubkey(1), 2, 10, &[2]);
+        let hash = older.hash(3);
+        dedup
+            .insert_filtered(3, 0, FilteredAccount::Matched(older.access(&hash)))
+            .unwrap();
+        dedup
+            .insert_filtered(4, 0, FilteredAccount::Rejected(&newer.meta))
+            .unwrap();
+        // A newer match supersedes an older rejection.
+        let matched = TestAccount::new(pubkey(2), 2, 10, &[3]);
+        dedup
+            .insert_filtered(3, 0, FilteredAccount::Rejected(&matched.meta))
+            .unwrap();
+        insert(&mut dedup, 4, &matched);
+        assert!(dedup.run_count() > 1);
+
+        assert_eq!(versions(dedup.finish().unwrap()), vec![(pubkey(2), 4, 2)]);
+    }
+
+    #[test]
+    fn deleted_accounts_shadow_older_versions() {
+        for keep_deleted in [false, true] {
+            let spill_dir = tempfile::tempdir().unwrap();
+            let mut dedup = AccountDeduplicator::new(spill_dir.path(), 0).unwrap();
+            dedup.keep_deleted(keep_deleted);
+            insert(&mut dedup, 3, &TestAccount::new(pubkey(1), 1, 10, &[1]));
+            insert(&mut dedup, 4, &TestAccount::new(pubkey(1), 2, 0, &[]));
+            insert(&mut dedup, 4, &TestAccount::new(pubkey(2), 3, 10, &[2]));
+
+            let expected = if keep_deleted {
+                vec![(pubkey(1), 4, 2), (pubkey(2), 4, 3)]
+            } else {
+                vec![(pubkey(2), 4, 3)]
+            };
+            assert_eq!(versions(dedup.finish().unwrap()), expected);
+        }
+    }
+
+    #[test]
+    fn returns_in_memory_accounts_in_pubkey_order() {
+        let spill_dir = tempfile::tempdir().unwrap();
+        let mut dedup = AccountDeduplicator::new(spill_dir.path(), usize::MAX).unwrap();
+        for n in [5, 1, 4, 2, 3] {
+            insert(
+                &mut dedup,
+                1,
+                &TestAccount::new(pubkey(n), n as u64, 10, &[]),
+            );
+        }
+        assert_eq!(dedup.run_count(), 0);
+        let pubkeys = versions(dedup.finish().unwrap())
+            .into_iter()
+            .map(|(pubkey, _, _)| pubkey)
+            .collect::<Vec<_>>();
+        assert_eq!(pubkeys, (1..=5).map(pubkey).collect::<Vec<_>>());
+    }
+}
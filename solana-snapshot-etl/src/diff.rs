@@ -0,0 +1,211 @@
+use crate::dedup::LatestAccount;
+use crate::Result;
+use solana_sdk::pubkey::Pubkey;
+use std::cmp::Ordering;
+use std::ops::Range;
+
+/// How an account changed between two snapshots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffKind {
+    Created,
+    Deleted,
+    Modified,
+}
+
+impl DiffKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiffKind::Created => "created",
+            DiffKind::Deleted => "deleted",
+            DiffKind::Modified => "modified",
+        }
+    }
+}
+
+/// Account that was created, deleted or modified between two snapshots.
+#[derive(Clone, Debug)]
+pub struct AccountDiff {
+    pub kind: DiffKind,
+    /// Latest version in the old snapshot, `None` if the account was created.
+    pub old: Option<LatestAccount>,
+    /// Latest version in the new snapshot, `None` if the account was deleted.
+    pub new: Option<LatestAccount>,
+    /// Byte ranges of the data that changed, including bytes added or removed at the end.
+    pub data_ranges: Vec<Range<usize>>,
+}
+
+impl AccountDiff {
+    fn new(old: Option<LatestAccount>, new: Option<LatestAccount>) -> Self {
+        let kind = match (&old, &new) {
+            (None, _) => DiffKind::Created,
+            (_, None) => DiffKind::Deleted,
+            _ => DiffKind::Modified,
+        };
+        let empty = Vec::new();
+        let old_data = old.as_ref().map_or(&empty, |account| &account.data);
+        let new_data = new.as_ref().map_or(&empty, |account| &account.data);
+        let data_ranges = changed_ranges(old_data, new_data);
+        Self {
+            kind,
+            old,
+            new,
+            data_ranges,
+        }
+    }
+
+    pub fn pubkey(&self) -> Pubkey {
+        self.old
+            .as_ref()
+            .or(self.new.as_ref())
+            .map(|account| account.meta.pubkey)
+            .expect("diff without accounts")
+    }
+
+    pub fn old_lamports(&self) -> u64 {
+        self.old.as_ref().map_or(0, |a| a.account_meta.lamports)
+    }
+
+    pub fn new_lamports(&self) -> u64 {
+        self.new.as_ref().map_or(0, |a| a.account_meta.lamports)
+    }
+
+    pub fn lamports_delta(&self) -> i128 {
+        self.new_lamports() as i128 - self.old_lamports() as i128
+    }
+
+    /// Returns whether both versions exist and have different owners.
+    pub fn owner_changed(&self) -> bool {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => old.account_meta.owner != new.account_meta.owner,
+            _ => false,
+        }
+    }
+
+    /// Returns whether both versions exist and only one of them is executable.
+    pub fn executable_changed(&self) -> bool {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => old.account_meta.executable != new.account_meta.executable,
+            _ => false,
+        }
+    }
+
+    pub fn data_changed(&self) -> bool {
+        !self.data_ranges.is_empty()
+    }
+
+    fn has_changes(&self) -> bool {
+        self.kind != DiffKind::Modified
+            || self.lamports_delta() != 0
+            || self.owner_changed()
+            || self.executable_changed()
+            || self.data_changed()
+    }
+}
+
+/// Returns the maximal byte ranges in which `old` and `new` differ.
+pub fn changed_ranges(old: &[u8], new: &[u8]) -> Vec<Range<usize>> {
+    let common = old.len().min(new.len());
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for i in 0..common {
+        match (old[i] != new[i], start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                ranges.push(s..i);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    let end = old.len().max(new.len());
+    match start {
+        Some(s) => ranges.push(s..end),
+        None if common < end => ranges.push(common..end),
+        None => {}
+    }
+    ranges
+}
+
+/// Compares the latest accounts of two snapshots, both in ascending pubkey order,
+/// as returned by `AccountDeduplicator::finish`.
+///
+/// Zero-lamport accounts count as deleted.
+/// Accounts whose lamports, owner, executable flag and data are unchanged are skipped,
+/// even if they were rewritten (e.g. by rent collection).
+pub fn diff_accounts<A, B>(old: A, new: B) -> AccountDiffs<A, B>
+where
+    A: Iterator<Item = Result<LatestAccount>>,
+    B: Iterator<Item = Result<LatestAccount>>,
+{
+    AccountDiffs {
+        old,
+        new,
+        old_head: None,
+        new_head: None,
+    }
+}
+
+pub struct AccountDiffs<A, B> {
+    old: A,
+    new: B,
+    old_head: Option<LatestAccount>,
+    new_head: Option<LatestAccount>,
+}
+
+/// Fills `head` with the next account that exists, i.e. has a non-zero balance.
+fn fill_head<I>(iter: &mut I, head: &mut Option<LatestAccount>) -> Result<()>
+where
+    I: Iterator<Item = Result<LatestAccount>>,
+{
+    while head.is_none() {
+        match iter.next() {
+            Some(account) => {
+                let account = account?;
+                if account.account_meta.lamports != 0 {
+                    *head = Some(account);
+                }
+            }
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+impl<A, B> AccountDiffs<A, B>
+where
+    A: Iterator<Item = Result<LatestAccount>>,
+    B: Iterator<Item = Result<LatestAccount>>,
+{
+    fn next_diff(&mut self) -> Result<Option<AccountDiff>> {
+        loop {
+            fill_head(&mut self.old, &mut self.old_head)?;
+            fill_head(&mut self.new, &mut self.new_head)?;
+            let order = match (&self.old_head, &self.new_head) {
+                (None, None) => return Ok(None),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(old), Some(new)) => old.meta.pubkey.cmp(&new.meta.pubkey),
+            };
+            let diff = match order {
+                Ordering::Less => AccountDiff::new(self.old_head.take(), None),
+                Ordering::Greater => AccountDiff::new(None, self.new_head.take()),
+                Ordering::Equal => AccountDiff::new(self.old_head.take(), self.new_head.take()),
+            };
+            if diff.has_changes() {
+                return Ok(Some(diff));
+            }
+        }
+    }
+}
+
+impl<A, B> Iterator for AccountDiffs<A, B>
+where
+    A: Iterator<Item = Result<LatestAccount>>,
+    B: Iterator<Item = Result<LatestAccount>>,
+{
+    type Item = Result<AccountDiff>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_diff().transpose()
+    }
+}
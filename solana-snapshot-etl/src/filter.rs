@@ -0,0 +1,401 @@
+use crate::append_vec::{AccountMeta, StoredAccountMeta, StoredMeta};
+use solana_sdk::hash::{Hash, Hasher};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+
+/// Predicate over stored accounts, applied while walking AppendVecs.
+///
+/// Filters are evaluated in stages as more of an account is read:
+/// first the pubkey, then the stored header, then the account data.
+/// Accounts rejected before the data stage are skipped without copying their data.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum AccountFilter {
+    /// Matches every account.
+    #[default]
+    All,
+    /// Account is owned by one of the given programs.
+    Owner(HashSet<Pubkey>),
+    /// Account address is one of the given pubkeys.
+    Pubkey(HashSet<Pubkey>),
+    /// Account data length is within the range.
+    DataSize(RangeInclusive<u64>),
+    /// Account data contains `bytes` at `offset`, like the `getProgramAccounts` memcmp filter.
+    Memcmp {
+        offset: usize,
+        bytes: Vec<u8>,
+    },
+    /// Account balance is within the range.
+    Lamports(RangeInclusive<u64>),
+    /// Account is (or is not) executable.
+    Executable(bool),
+    /// Account has (or does not have) a zero balance, i.e. was deleted.
+    ZeroLamport(bool),
+    And(Vec<AccountFilter>),
+    Or(Vec<AccountFilter>),
+    Not(Box<AccountFilter>),
+}
+
+/// Stored account seen by a filtered account walk.
+pub enum FilteredAccount<'a> {
+    /// Account matched the filter.
+    Matched(StoredAccountMeta<'a>),
+    /// Account did not match the filter, and its data was not read.
+    ///
+    /// Reported so that consumers keeping the latest version of each account
+    /// can tell when a newer version no longer matches.
+    /// Accounts rejected by their pubkey alone are not reported,
+    /// since no other version of them can match either.
+    Rejected(&'a StoredMeta),
+}
+
+/// Parts of an account known at a given evaluation stage.
+struct Probe<'a> {
+    pubkey: &'a Pubkey,
+    header: Option<(&'a StoredMeta, &'a AccountMeta)>,
+    data: Option<&'a [u8]>,
+}
+
+impl AccountFilter {
+    /// Combines two filters, matching accounts that match both.
+    pub fn and(self, other: AccountFilter) -> AccountFilter {
+        match (self, other) {
+            (AccountFilter::All, other) | (other, AccountFilter::All) => other,
+            (AccountFilter::And(mut filters), AccountFilter::And(others)) => {
+                filters.extend(others);
+                AccountFilter::And(filters)
+            }
+            (AccountFilter::And(mut filters), other) => {
+                filters.push(other);
+                AccountFilter::And(filters)
+            }
+            (filter, other) => AccountFilter::And(vec![filter, other]),
+        }
+    }
+
+    /// Returns whether the filter asks for zero-lamport accounts with `ZeroLamport(true)`.
+    pub fn selects_zero_lamport(&self) -> bool {
+        match self {
+            AccountFilter::ZeroLamport(zero) => *zero,
+            AccountFilter::And(filters) | AccountFilter::Or(filters) => {
+                filters.iter().any(AccountFilter::selects_zero_lamport)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns whether the filter decides on every account by its pubkey alone,
+    /// so that all versions of an account are either matched or rejected together.
+    pub fn is_pubkey_only(&self) -> bool {
+        match self {
+            AccountFilter::All | AccountFilter::Pubkey(_) => true,
+            AccountFilter::And(filters) | AccountFilter::Or(filters) => {
+                filters.iter().all(AccountFilter::is_pubkey_only)
+            }
+            AccountFilter::Not(filter) => filter.is_pubkey_only(),
+            _ => false,
+        }
+    }
+
+    /// Decides on an account by its pubkey, or returns `None` if more is needed.
+    pub fn matches_pubkey(&self, pubkey: &Pubkey) -> Option<bool> {
+        self.eval(&Probe {
+            pubkey,
+            header: None,
+            data: None,
+        })
+    }
+
+    /// Decides on an account by its stored header, or returns `None` if its data is needed.
+    pub fn matches_header(&self, meta: &StoredMeta, account_meta: &AccountMeta) -> Option<bool> {
+        self.eval(&Probe {
+            pubkey: &meta.pubkey,
+            header: Some((meta, account_meta)),
+            data: None,
+        })
+    }
+
+    pub fn matches(&self, account: &StoredAccountMeta) -> bool {
+        self.eval(&Probe {
+            pubkey: &account.meta.pubkey,
+            header: Some((account.meta, account.account_meta)),
+            data: Some(account.data),
+        })
+        .unwrap_or(false)
+    }
+
+    /// Classifies an account, or returns `None` if it is rejected by its pubkey alone.
+    pub fn apply<'a>(&self, account: StoredAccountMeta<'a>) -> Option<FilteredAccount<'a>> {
+        if self.matches(&account) {
+            Some(FilteredAccount::Matched(account))
+        } else if self.matches_pubkey(&account.meta.pubkey) == Some(false) {
+            None
+        } else {
+            Some(FilteredAccount::Rejected(account.meta))
+        }
+    }
+
+    /// Hashes the filter, so that equal filters have the same fingerprint across runs.
+    pub fn fingerprint(&self) -> Hash {
+        let mut hasher = Hasher::default();
+        self.hash_into(&mut hasher);
+        hasher.result()
+    }
+
+    fn hash_into(&self, hasher: &mut Hasher) {
+        fn hash_pubkeys(hasher: &mut Hasher, pubkeys: &HashSet<Pubkey>) {
+            let mut pubkeys: Vec<_> = pubkeys.iter().collect();
+            pubkeys.sort_unstable();
+            hasher.hash(&(pubkeys.len() as u64).to_le_bytes());
+            for pubkey in pubkeys {
+                hasher.hash(pubkey.as_ref());
+            }
+        }
+        fn hash_range(hasher: &mut Hasher, range: &RangeInclusive<u64>) {
+            hasher.hash(&range.start().to_le_bytes());
+            hasher.hash(&range.end().to_le_bytes());
+        }
+
+        match self {
+            AccountFilter::All => hasher.hash(&[0]),
+            AccountFilter::Owner(owners) => {
+                hasher.hash(&[1]);
+                hash_pubkeys(hasher, owners);
+            }
+            AccountFilter::Pubkey(pubkeys) => {
+                hasher.hash(&[2]);
+                hash_pubkeys(hasher, pubkeys);
+            }
+            AccountFilter::DataSize(range) => {
+                hasher.hash(&[3]);
+                hash_range(hasher, range);
+            }
+            AccountFilter::Memcmp { offset, bytes } => {
+                hasher.hash(&[4]);
+                hasher.hash(&(*offset as u64).to_le_bytes());
+                hasher.hash(&(bytes.len() as u64).to_le_bytes());
+                hasher.hash(bytes);
+            }
+            AccountFilter::Lamports(range) => {
+                hasher.hash(&[5]);
+                hash_range(hasher, range);
+            }
+            AccountFilter::Executable(executable) => hasher.hash(&[6, *executable as u8]),
+            AccountFilter::ZeroLamport(zero) => hasher.hash(&[7, *zero as u8]),
+            AccountFilter::And(filters) | AccountFilter::Or(filters) => {
+                let tag = if matches!(self, AccountFilter::And(_)) {
+                    8
+                } else {
+                    9
+                };
+                hasher.hash(&[tag]);
+                hasher.hash(&(filters.len() as u64).to_le_bytes());
+                for filter in filters {
+                    filter.hash_into(hasher);
+                }
+            }
+            AccountFilter::Not(filter) => {
+                hasher.hash(&[10]);
+                filter.hash_into(hasher);
+            }
+        }
+    }
+
+    fn eval(&self, probe: &Probe) -> Option<bool> {
+        match self {
+            AccountFilter::All => Some(true),
+            AccountFilter::Owner(owners) => probe
+                .header
+                .map(|(_, account_meta)| owners.contains(&account_meta.owner)),
+            AccountFilter::Pubkey(pubkeys) => Some(pubkeys.contains(probe.pubkey)),
+            AccountFilter::DataSize(range) => {
+                probe.header.map(|(meta, _)| range.contains(&meta.data_len))
+            }
+            AccountFilter::Memcmp { offset, bytes } => {
+                let end = offset.saturating_add(bytes.len());
+                match (probe.data, probe.header) {
+                    (Some(data), _) => Some(data.get(*offset..end) == Some(bytes.as_slice())),
+                    (None, Some((meta, _))) if end as u64 > meta.data_len => Some(false),
+                    _ => None,
+                }
+            }
+            AccountFilter::Lamports(range) => probe
+                .header
+                .map(|(_, account_meta)| range.contains(&account_meta.lamports)),
+            AccountFilter::Executable(executable) => probe
+                .header
+                .map(|(_, account_meta)| account_meta.executable == *executable),
+            AccountFilter::ZeroLamport(zero) => probe
+                .header
+                .map(|(_, account_meta)| (account_meta.lamports == 0) == *zero),
+            AccountFilter::And(filters) => {
+                let mut result = Some(true);
+                for filter in filters {
+                    match filter.eval(probe) {
+                        Some(false) => return Some(false),
+                        None => result = None,
+                        Some(true) => {}
+                    }
+                }
+                result
+            }
+            AccountFilter::Or(filters) => {
+                let mut result = Some(false);
+                for filter in filters {
+                    match filter.eval(probe) {
+                        Some(true) => return Some(true),
+                        None => result = None,
+                        Some(false) => {}
+                    }
+                }
+                result
+            }
+            AccountFilter::Not(filter) => filter.eval(probe).map(|matched| !matched),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{pubkey, TestAccount};
+    use solana_sdk::hash::Hash;
+
+    /// Evaluates the filter at the pubkey, header and data stages.
+    fn stages(filter: &AccountFilter, account: &TestAccount) -> [Option<bool>; 3] {
+        let hash = Hash::default();
+        [
+            filter.matches_pubkey(&account.meta.pubkey),
+            filter.matches_header(&account.meta, &account.account_meta),
+            Some(filter.matches(&account.access(&hash))),
+        ]
+    }
+
+    fn memcmp(offset: usize, bytes: &[u8]) -> AccountFilter {
+        AccountFilter::Memcmp {
+            offset,
+            bytes: bytes.to_vec(),
+        }
+    }
+
+    #[test]
+    fn memcmp_past_the_end_of_the_data() {
+        let account = TestAccount::new(pubkey(1), 1, 10, &[1, 2, 3, 4]);
+        assert_eq!(
+            stages(&memcmp(2, &[3, 4]), &account),
+            [None, None, Some(true)]
+        );
+        // Bytes that end past the data are rejected from the header alone.
+        for filter in [
+            memcmp(3, &[4, 0]),
+            memcmp(4, &[0]),
+            memcmp(100, &[0]),
+            memcmp(usize::MAX, &[0]),
+        ] {
+            assert_eq!(stages(&filter, &account), [None, Some(false), Some(false)]);
+        }
+        assert_eq!(stages(&memcmp(4, &[]), &account), [None, None, Some(true)]);
+    }
+
+    #[test]
+    fn combinators_defer_until_decided() {
+        let account = TestAccount::new(pubkey(1), 1, 10, &[1, 2, 3, 4]);
+        let this_pubkey = || AccountFilter::Pubkey(HashSet::from([pubkey(1)]));
+        let other_pubkey = || AccountFilter::Pubkey(HashSet::from([pubkey(2)]));
+        let rich = || AccountFilter::Lamports(5..=u64::MAX);
+        let data = || memcmp(0, &[1]);
+        let not = |filter| AccountFilter::Not(Box::new(filter));
+
+        let cases = [
+            (
+                AccountFilter::And(vec![rich(), other_pubkey()]),
+                [Some(false), Some(false), Some(false)],
+            ),
+            (
+                AccountFilter::And(vec![this_pubkey(), rich()]),
+                [None, Some(true), Some(true)],
+            ),
+            (
+                AccountFilter::And(vec![rich(), data()]),
+                [None, None, Some(true)],
+            ),
+            (
+                AccountFilter::Or(vec![data(), this_pubkey()]),
+                [Some(true), Some(true), Some(true)],
+            ),
+            (
+                AccountFilter::Or(vec![other_pubkey(), rich()]),
+                [None, Some(true), Some(true)],
+            ),
+            (
+                AccountFilter::Or(vec![other_pubkey(), not(data())]),
+                [None, None, Some(false)],
+            ),
+            (not(rich()), [None, Some(false), Some(false)]),
+            (not(this_pubkey()), [Some(false), Some(false), Some(false)]),
+            (
+                not(AccountFilter::And(vec![this_pubkey(), data()])),
+                [None, None, Some(false)],
+            ),
+        ];
+        for (filter, expected) in cases {
+            assert_eq!(stages(&filter, &account), expected, "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn fingerprint_is_stable_across_equal_filters() {
+        let pubkeys = (1..=50).map(pubkey).collect::<Vec<_>>();
+        let filter = |pubkeys: &[Pubkey]| {
+            AccountFilter::Pubkey(pubkeys.iter().copied().collect())
+                .and(AccountFilter::Owner(pubkeys.iter().copied().collect()))
+                .and(memcmp(8, &[1, 2]))
+        };
+        let reversed = pubkeys.iter().rev().copied().collect::<Vec<_>>();
+        assert_eq!(filter(&pubkeys), filter(&reversed));
+        assert_eq!(
+            filter(&pubkeys).fingerprint(),
+            filter(&reversed).fingerprint()
+        );
+
+        let different = [
+            AccountFilter::All,
+            filter(&pubkeys[1..]),
+            AccountFilter::Owner(pubkeys.iter().copied().collect()),
+            AccountFilter::And(vec![AccountFilter::All]),
+            AccountFilter::Or(vec![AccountFilter::All]),
+            AccountFilter::Not(Box::new(AccountFilter::All)),
+            AccountFilter::Lamports(0..=1),
+            AccountFilter::DataSize(0..=1),
+            memcmp(8, &[1]),
+            memcmp(9, &[1]),
+        ];
+        let fingerprints = different
+            .iter()
+            .map(AccountFilter::fingerprint)
+            .chain([filter(&pubkeys).fingerprint()])
+            .collect::<HashSet<_>>();
+        assert_eq!(fingerprints.len(), different.len() + 1);
+    }
+
+    #[test]
+    fn classifies_filters() {
+        let pubkeys = || AccountFilter::Pubkey(HashSet::from([pubkey(1)]));
+        assert!(AccountFilter::All.is_pubkey_only());
+        assert!(
+            AccountFilter::Or(vec![pubkeys(), AccountFilter::Not(Box::new(pubkeys()))])
+                .is_pubkey_only()
+        );
+        assert!(!pubkeys()
+            .and(AccountFilter::Executable(true))
+            .is_pubkey_only());
+
+        assert!(pubkeys()
+            .and(AccountFilter::ZeroLamport(true))
+            .selects_zero_lamport());
+        assert!(!AccountFilter::ZeroLamport(false).selects_zero_lamport());
+        assert!(
+            !AccountFilter::Not(Box::new(AccountFilter::ZeroLamport(true))).selects_zero_lamport()
+        );
+    }
+}
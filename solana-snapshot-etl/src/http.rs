@@ -0,0 +1,377 @@
+use crate::{Result, SnapshotError};
+use log::{info, warn};
+use reqwest::blocking::{Client, Request, Response};
+use reqwest::header::{CONTENT_RANGE, ETAG, IF_RANGE, RANGE};
+use reqwest::StatusCode;
+use std::io::Read;
+use std::time::Duration;
+
+/// How often and how patiently `HttpSource` reconnects after a failure.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Number of consecutive failed attempts after which the error is returned.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled on every further retry.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 10,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX))
+            .min(self.max_backoff)
+    }
+}
+
+/// Authenticates each request sent by `HttpSource`.
+///
+/// Called again for every reconnect, so signatures that expire stay fresh.
+pub trait RequestSigner: Send + Sync {
+    fn sign(&self, request: &mut Request) -> Result<()>;
+}
+
+/// Reads a file over HTTP, resuming with range requests when the connection drops.
+///
+/// The length and ETag of the first response are remembered,
+/// and every later response must match them so that the stream cannot change underneath the reader.
+/// Servers that do not honor range requests cannot be resumed from a non-zero offset.
+pub struct HttpSource {
+    client: Client,
+    url: String,
+    policy: RetryPolicy,
+    signer: Option<Box<dyn RequestSigner>>,
+    response: Option<Response>,
+    offset: u64,
+    len: Option<u64>,
+    etag: Option<String>,
+}
+
+/// Failure of a single attempt, which is only retried if `transient`.
+struct Failure {
+    err: SnapshotError,
+    transient: bool,
+}
+
+impl Failure {
+    fn transient(err: impl Into<SnapshotError>) -> Self {
+        Self {
+            err: err.into(),
+            transient: true,
+        }
+    }
+
+    fn permanent(err: impl Into<SnapshotError>) -> Self {
+        Self {
+            err: err.into(),
+            transient: false,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Failure {
+    fn from(err: reqwest::Error) -> Self {
+        let transient = err.is_connect() || err.is_timeout() || err.is_body() || err.is_request();
+        Self {
+            err: err.into(),
+            transient,
+        }
+    }
+}
+
+impl HttpSource {
+    /// Sends the first request with a default client and retry policy.
+    pub fn open(url: &str) -> Result<Self> {
+        Self::with_client(Client::new(), url, RetryPolicy::default())
+    }
+
+    pub fn with_client(client: Client, url: &str, policy: RetryPolicy) -> Result<Self> {
+        Self::with_signer(client, url, policy, None)
+    }
+
+    pub fn with_signer(
+        client: Client,
+        url: &str,
+        policy: RetryPolicy,
+        signer: Option<Box<dyn RequestSigner>>,
+    ) -> Result<Self> {
+        let mut source = Self {
+            client,
+            url: url.to_owned(),
+            policy,
+            signer,
+            response: None,
+            offset: 0,
+            len: None,
+            etag: None,
+        };
+        source.with_retries(|source| source.connect())?;
+        Ok(source)
+    }
+
+    /// Returns the number of bytes read so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the length of the file, if the server reported it.
+    pub fn content_length(&self) -> Option<u64> {
+        self.len
+    }
+
+    /// Runs `f` until it succeeds, fails permanently, or runs out of retries.
+    fn with_retries<T>(
+        &mut self,
+        mut f: impl FnMut(&mut Self) -> std::result::Result<T, Failure>,
+    ) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            match f(self) {
+                Ok(value) => return Ok(value),
+                Err(failure) if !failure.transient || attempt >= self.policy.max_retries => {
+                    return Err(failure.err)
+                }
+                Err(failure) => {
+                    let backoff = self.policy.backoff(attempt);
+                    warn!(
+                        "HTTP stream failed at byte {}, retrying in {:?}: {}",
+                        self.offset, backoff, failure.err
+                    );
+                    self.response = None;
+                    std::thread::sleep(backoff);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Requests the rest of the file from the current offset.
+    fn connect(&mut self) -> std::result::Result<(), Failure> {
+        let mut request = self.client.get(&self.url);
+        if self.offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", self.offset));
+            // Weak validators are not allowed in If-Range.
+            if let Some(etag) = self.etag.as_ref().filter(|etag| !etag.starts_with("W/")) {
+                request = request.header(IF_RANGE, etag);
+            }
+        }
+        let mut request = request.build()?;
+        if let Some(signer) = &self.signer {
+            signer.sign(&mut request).map_err(Failure::permanent)?;
+        }
+        let response = self.client.execute(request)?;
+        let status = response.status();
+        if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            return Err(Failure::transient(response.error_for_status().unwrap_err()));
+        }
+        let response = response.error_for_status()?;
+
+        let len = match (self.offset, status) {
+            (0, StatusCode::OK) => response.content_length(),
+            (_, StatusCode::PARTIAL_CONTENT) => {
+                let (start, len) = response
+                    .headers()
+                    .get(CONTENT_RANGE)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(parse_content_range)
+                    .ok_or_else(|| self.changed("missing or invalid Content-Range"))?;
+                if start != self.offset {
+                    return Err(self.changed(format!(
+                        "requested range from byte {} but got byte {}",
+                        self.offset, start
+                    )));
+                }
+                len
+            }
+            (_, StatusCode::OK) => {
+                return Err(self.changed("server ignored the range request, or the file changed"))
+            }
+            (_, status) => return Err(self.changed(format!("unexpected status {}", status))),
+        };
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
+        if self.offset == 0 {
+            info!("HTTP source has {:?} bytes, ETag {:?}", len, etag);
+        }
+        if self.len.is_some() && len != self.len {
+            return Err(self.changed(format!("length changed from {:?} to {:?}", self.len, len)));
+        }
+        if self.etag.is_some() && etag != self.etag {
+            return Err(self.changed(format!("ETag changed from {:?} to {:?}", self.etag, etag)));
+        }
+        self.len = len;
+        self.etag = etag;
+        self.response = Some(response);
+        Ok(())
+    }
+
+    fn changed(&self, reason: impl Into<String>) -> Failure {
+        Failure::permanent(SnapshotError::SourceChanged {
+            url: self.url.clone(),
+            reason: reason.into(),
+        })
+    }
+
+    fn read_attempt(&mut self, buf: &mut [u8]) -> std::result::Result<usize, Failure> {
+        if self.response.is_none() {
+            self.connect()?;
+        }
+        let response = self.response.as_mut().unwrap();
+        let n = loop {
+            match response.read(buf) {
+                Ok(n) => break n,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(Failure::transient(err)),
+            }
+        };
+        if n == 0 && !buf.is_empty() && self.len.is_some_and(|len| self.offset < len) {
+            return Err(Failure::transient(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "connection closed before end of file",
+            )));
+        }
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+impl Read for HttpSource {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.with_retries(|source| source.read_attempt(buf))
+            .map_err(|err| match err {
+                SnapshotError::IOError(err) => err,
+                err => std::io::Error::other(err),
+            })
+    }
+}
+
+/// Parses `bytes <start>-<end>/<len>` into the start offset and total length.
+fn parse_content_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (range, len) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _end) = range.split_once('-')?;
+    let len = match len {
+        "*" => None,
+        len => Some(len.parse().ok()?),
+    };
+    Some((start.parse().ok()?, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn parses_content_range() {
+        assert_eq!(parse_content_range("bytes 0-99/100"), Some((0, Some(100))));
+        assert_eq!(
+            parse_content_range("bytes 4096-8191/1048576"),
+            Some((4096, Some(1048576)))
+        );
+        assert_eq!(parse_content_range("bytes 42-99/*"), Some((42, None)));
+        assert_eq!(parse_content_range("bytes */100"), None);
+        assert_eq!(parse_content_range("bytes 0-99"), None);
+        assert_eq!(parse_content_range("items 0-99/100"), None);
+        assert_eq!(parse_content_range("bytes x-99/100"), None);
+        assert_eq!(parse_content_range("bytes 0-99/x"), None);
+    }
+
+    /// Range and If-Range headers of the requests served so far.
+    type Recorded = Arc<Mutex<Vec<(String, String)>>>;
+
+    /// Serves `body`, dropping the first connection halfway through it,
+    /// and records the Range and If-Range headers of every request.
+    fn serve_with_drop(body: Vec<u8>) -> (String, Recorded) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/snapshot.tar.zst", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+        std::thread::spawn(move || {
+            for (i, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                let (mut range, mut if_range) = (String::new(), String::new());
+                for line in BufReader::new(&stream).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    let (name, value) = line.split_once(": ").unwrap_or((&line, ""));
+                    match name.to_ascii_lowercase().as_str() {
+                        "range" => range = value.to_owned(),
+                        "if-range" => if_range = value.to_owned(),
+                        _ => {}
+                    }
+                }
+                recorded.lock().unwrap().push((range.clone(), if_range));
+
+                let start: usize = range
+                    .strip_prefix("bytes=")
+                    .and_then(|range| range.strip_suffix('-'))
+                    .map_or(0, |start| start.parse().unwrap());
+                let status = if start == 0 {
+                    "200 OK".to_owned()
+                } else {
+                    format!(
+                        "206 Partial Content\r\nContent-Range: bytes {}-{}/{}",
+                        start,
+                        body.len() - 1,
+                        body.len()
+                    )
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len() - start
+                )
+                .unwrap();
+                let end = if i == 0 { body.len() / 2 } else { body.len() };
+                stream.write_all(&body[start..end]).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    #[test]
+    fn resumes_after_dropped_connection() {
+        let body: Vec<u8> = (0..1 << 20).map(|i: u32| (i % 251) as u8).collect();
+        let (url, requests) = serve_with_drop(body.clone());
+        let policy = RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(10),
+        };
+        let mut source = HttpSource::with_client(Client::new(), &url, policy).unwrap();
+        assert_eq!(source.content_length(), Some(body.len() as u64));
+
+        let mut read = Vec::new();
+        source.read_to_end(&mut read).unwrap();
+        assert_eq!(read.len(), body.len());
+        assert!(read == body);
+        assert_eq!(source.offset(), body.len() as u64);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0], (String::new(), String::new()));
+        assert_eq!(
+            requests[1],
+            (format!("bytes={}-", body.len() / 2), "\"v1\"".to_owned())
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use crate::filter::AccountFilter;
+use crate::status_cache::SlotStatuses;
+use crate::{
+    AppendVecIterator, CorruptionPolicy, FilteredAccountCallback, Result, SnapshotError,
+    SnapshotExtractor, SnapshotManifest,
+};
+use std::collections::HashSet;
+
+/// Layers an incremental snapshot on top of the full snapshot it is based on.
+///
+/// Yields the AppendVecs of the full snapshot, followed by those of the incremental snapshot.
+/// The incremental snapshot only contains accounts written after the full snapshot slot,
+/// so keeping the latest version of each account yields the state at the incremental slot.
+pub struct IncrementalSnapshotExtractor<Full, Incremental>
+where
+    Full: SnapshotExtractor,
+    Incremental: SnapshotExtractor,
+{
+    full: Full,
+    incremental: Incremental,
+}
+
+impl<Full, Incremental> SnapshotExtractor for IncrementalSnapshotExtractor<Full, Incremental>
+where
+    Full: SnapshotExtractor,
+    Incremental: SnapshotExtractor,
+{
+    fn iter(&mut self) -> AppendVecIterator<'_> {
+        Box::new(self.full.iter().chain(self.incremental.iter()))
+    }
+
+    /// Returns the manifest of the incremental snapshot.
+    fn manifest(&self) -> &SnapshotManifest {
+        self.incremental.manifest()
+    }
+
+    /// Returns the status cache of the incremental snapshot.
+    fn status_cache(&self) -> Result<Option<Vec<SlotStatuses>>> {
+        self.incremental.status_cache()
+    }
+
+    fn skip_append_vecs(&mut self, append_vecs: HashSet<(u64, u64)>) {
+        self.full.skip_append_vecs(append_vecs.clone());
+        self.incremental.skip_append_vecs(append_vecs);
+    }
+
+    fn for_each_filtered_account(
+        &mut self,
+        policy: CorruptionPolicy,
+        filter: &AccountFilter,
+        f: &mut FilteredAccountCallback<'_>,
+    ) -> Result<()> {
+        self.full.for_each_filtered_account(policy, filter, f)?;
+        self.incremental
+            .for_each_filtered_account(policy, filter, f)
+    }
+}
+
+impl<Full, Incremental> IncrementalSnapshotExtractor<Full, Incremental>
+where
+    Full: SnapshotExtractor,
+    Incremental: SnapshotExtractor,
+{
+    pub fn new(full: Full, incremental: Incremental) -> Result<Self> {
+        let full_slot = full.manifest().slot();
+        let incremental_manifest = incremental.manifest();
+        let incremental_slot = incremental_manifest.slot();
+        // Incremental snapshots that record their full snapshot must name this one.
+        // `Option::is_none_or` would need Rust 1.82.
+        #[allow(clippy::unnecessary_map_or)]
+        let persisted_full_matches = incremental_manifest
+            .extra_fields
+            .incremental_snapshot_persistence
+            .as_ref()
+            .map_or(true, |persistence| {
+                persistence.full_slot == full_slot
+                    && persistence.full_hash == *full.manifest().accounts_hash()
+            });
+        let applies = incremental_slot > full_slot
+            && persisted_full_matches
+            && incremental_manifest
+                .accounts_db_fields
+                .0
+                .keys()
+                .all(|&slot| slot > full_slot);
+        if !applies {
+            return Err(SnapshotError::IncrementalSnapshotMismatch {
+                full_slot,
+                incremental_slot,
+            });
+        }
+        Ok(Self { full, incremental })
+    }
+
+    pub fn full(&self) -> &Full {
+        &self.full
+    }
+
+    pub fn incremental(&self) -> &Incremental {
+        &self.incremental
+    }
+}
@@ -0,0 +1,293 @@
+use crate::solana::deserialize_from;
+use crate::{CorruptionPolicy, Result, SnapshotError, SnapshotExtractor};
+use log::info;
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use solana_sdk::clock::Slot;
+use solana_sdk::pubkey::Pubkey;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+const INDEX_MAGIC: &[u8; 8] = b"SSETLIX1";
+const HEADER_SIZE: usize = 24;
+const ENTRY_SIZE: usize = 56;
+
+/// Location of the latest version of an account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub pubkey: Pubkey,
+    pub slot: Slot,
+    pub id: u64,
+    pub offset: usize,
+}
+
+/// On-disk index mapping each pubkey to the latest version of the account.
+///
+/// The file holds a header (magic, snapshot slot, entry count),
+/// followed by fixed-size entries sorted by pubkey, which are binary searched in place.
+/// Accounts whose latest version has zero lamports are deleted and left out.
+pub struct AccountIndex {
+    map: Mmap,
+    slot: Slot,
+    len: usize,
+}
+
+impl AccountIndex {
+    /// Reads all accounts of a snapshot and writes the index to `path`.
+    ///
+    /// Entries are sorted in memory until `mem_budget` bytes are used,
+    /// then spilled into run files under `spill_dir` and merged.
+    pub fn build<E: SnapshotExtractor>(
+        extractor: &mut E,
+        policy: CorruptionPolicy,
+        path: &Path,
+        spill_dir: &Path,
+        mem_budget: usize,
+    ) -> Result<Self> {
+        let slot = extractor.manifest().slot();
+        let mut builder = IndexBuilder::new(spill_dir, mem_budget)?;
+        extractor.for_each_account(policy, &mut |slot, id, account| {
+            builder.push(BuildEntry {
+                pubkey: account.meta.pubkey,
+                slot,
+                write_version: account.meta.write_version,
+                id,
+                offset: account.offset as u64,
+                deleted: account.account_meta.lamports == 0,
+            })
+        })?;
+        builder.finish(path, slot)?;
+        Self::open(path)
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        let map = unsafe { Mmap::map(&file)? };
+        if map.len() < HEADER_SIZE || &map[..8] != INDEX_MAGIC {
+            return Err(SnapshotError::CorruptAccountIndex("bad header"));
+        }
+        let slot = u64::from_le_bytes(map[8..16].try_into().unwrap());
+        let len = u64::from_le_bytes(map[16..24].try_into().unwrap());
+        // A corrupt length must not overflow into a size that happens to match the file.
+        let size = usize::try_from(len)
+            .ok()
+            .and_then(|len| len.checked_mul(ENTRY_SIZE))
+            .and_then(|entries| entries.checked_add(HEADER_SIZE));
+        if size != Some(map.len()) {
+            return Err(SnapshotError::CorruptAccountIndex("bad length"));
+        }
+        let len = len as usize;
+        Ok(Self { map, slot, len })
+    }
+
+    /// Returns the slot of the snapshot the index was built from.
+    pub fn slot(&self) -> Slot {
+        self.slot
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, pubkey: &Pubkey) -> Option<IndexEntry> {
+        let (mut lo, mut hi) = (0, self.len);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let entry = self.entry(mid);
+            match entry.pubkey.cmp(pubkey) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(entry),
+            }
+        }
+        None
+    }
+
+    fn entry(&self, idx: usize) -> IndexEntry {
+        let pos = HEADER_SIZE + idx * ENTRY_SIZE;
+        let bytes = &self.map[pos..pos + ENTRY_SIZE];
+        let u64_at = |pos: usize| u64::from_le_bytes(bytes[pos..pos + 8].try_into().unwrap());
+        IndexEntry {
+            pubkey: Pubkey::new(&bytes[..32]),
+            slot: u64_at(32),
+            id: u64_at(40),
+            offset: u64_at(48) as usize,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct BuildEntry {
+    pubkey: Pubkey,
+    slot: Slot,
+    write_version: u64,
+    id: u64,
+    offset: u64,
+    deleted: bool,
+}
+
+impl BuildEntry {
+    fn version(&self) -> (Slot, u64) {
+        (self.slot, self.write_version)
+    }
+}
+
+struct IndexBuilder {
+    dir: TempDir,
+    max_entries: usize,
+    entries: Vec<BuildEntry>,
+    runs: Vec<(PathBuf, usize)>,
+}
+
+impl IndexBuilder {
+    fn new(spill_dir: &Path, mem_budget: usize) -> Result<Self> {
+        let dir = tempfile::Builder::new()
+            .prefix("account-index")
+            .tempdir_in(spill_dir)?;
+        Ok(Self {
+            dir,
+            max_entries: (mem_budget / std::mem::size_of::<BuildEntry>()).max(1),
+            entries: Vec::new(),
+            runs: Vec::new(),
+        })
+    }
+
+    fn push(&mut self, entry: BuildEntry) -> Result<()> {
+        self.entries.push(entry);
+        if self.entries.len() >= self.max_entries {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Sorts buffered entries by pubkey, keeping only the latest version of each.
+    fn sort_entries(&mut self) {
+        self.entries
+            .sort_unstable_by_key(|e| (e.pubkey, Reverse(e.version())));
+        self.entries.dedup_by_key(|e| e.pubkey);
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        self.sort_entries();
+        let path = self.dir.path().join(format!("run-{}", self.runs.len()));
+        info!(
+            "Spilling {} index entries to {:?}",
+            self.entries.len(),
+            &path
+        );
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for entry in &self.entries {
+            bincode::serialize_into(&mut writer, entry)?;
+        }
+        writer.flush()?;
+        self.runs.push((path, self.entries.len()));
+        self.entries.clear();
+        Ok(())
+    }
+
+    fn finish(mut self, path: &Path, slot: Slot) -> Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        writer.write_all(INDEX_MAGIC)?;
+        writer.write_all(&slot.to_le_bytes())?;
+        writer.write_all(&0u64.to_le_bytes())?;
+
+        let mut len = 0u64;
+        let mut write = |entry: &BuildEntry| -> Result<()> {
+            if !entry.deleted {
+                writer.write_all(entry.pubkey.as_ref())?;
+                writer.write_all(&entry.slot.to_le_bytes())?;
+                writer.write_all(&entry.id.to_le_bytes())?;
+                writer.write_all(&entry.offset.to_le_bytes())?;
+                len += 1;
+            }
+            Ok(())
+        };
+        if self.runs.is_empty() {
+            self.sort_entries();
+            for entry in &self.entries {
+                write(entry)?;
+            }
+        } else {
+            if !self.entries.is_empty() {
+                self.spill()?;
+            }
+            let mut readers = Vec::with_capacity(self.runs.len());
+            let mut heap = BinaryHeap::with_capacity(self.runs.len());
+            for (idx, (run_path, run_len)) in self.runs.iter().enumerate() {
+                let mut reader = (BufReader::new(File::open(run_path)?), *run_len);
+                if let Some(entry) = next_run_entry(&mut reader)? {
+                    heap.push(Reverse((entry.pubkey, Reverse(entry.version()), idx)));
+                    readers.push((reader, Some(entry)));
+                } else {
+                    readers.push((reader, None));
+                }
+            }
+            let mut latest: Option<BuildEntry> = None;
+            while let Some(Reverse((_, _, idx))) = heap.pop() {
+                let (reader, head) = &mut readers[idx];
+                let entry = head.take().expect("run has no head");
+                if let Some(next) = next_run_entry(reader)? {
+                    heap.push(Reverse((next.pubkey, Reverse(next.version()), idx)));
+                    *head = Some(next);
+                }
+                match &latest {
+                    Some(prev) if prev.pubkey == entry.pubkey => {}
+                    _ => {
+                        if let Some(prev) = latest.replace(entry) {
+                            write(&prev)?;
+                        }
+                    }
+                }
+            }
+            if let Some(prev) = latest {
+                write(&prev)?;
+            }
+        }
+
+        let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.seek(SeekFrom::Start(16))?;
+        file.write_all(&len.to_le_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+        info!("Wrote {} index entries to {:?}", len, path);
+        Ok(())
+    }
+}
+
+fn next_run_entry(run: &mut (BufReader<File>, usize)) -> Result<Option<BuildEntry>> {
+    if run.1 == 0 {
+        return Ok(None);
+    }
+    run.1 -= 1;
+    Ok(Some(deserialize_from(&mut run.0)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_overflowing_length() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index");
+        // Wrapping arithmetic would size 2^61 entries at 0 bytes, matching a header-only file.
+        let len = 1u64 << 61;
+        let mut bytes = INDEX_MAGIC.to_vec();
+        bytes.extend(7u64.to_le_bytes());
+        bytes.extend(len.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            AccountIndex::open(&path),
+            Err(SnapshotError::CorruptAccountIndex("bad length"))
+        ));
+    }
+}
@@ -1,27 +1,56 @@
+use log::warn;
+use solana_runtime::snapshot_utils::SnapshotVersion;
 use std::cell::RefCell;
+use std::collections::HashSet;
 use std::ffi::OsStr;
 use std::io::Read;
+use std::ops::Deref;
 use std::path::Path;
 use std::rc::Rc;
 use std::str::FromStr;
+use std::sync::Arc;
 use thiserror::Error;
 
 pub mod append_vec;
+pub mod checkpoint;
+pub mod decompress;
+pub mod dedup;
+pub mod diff;
+pub mod filter;
+pub mod index;
+pub mod manifest;
 pub mod solana;
+pub mod stakes;
+pub mod status_cache;
+pub mod stream;
+pub mod unpack;
+pub mod verify;
+pub mod writer;
 
 pub mod archived;
+pub mod incremental;
 pub mod unpacked;
 
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "async")]
+pub mod nonblocking;
 #[cfg(feature = "parallel")]
 pub mod parallel;
+#[cfg(feature = "parallel")]
+pub mod pipeline;
+#[cfg(feature = "s3")]
+pub mod s3;
+#[cfg(test)]
+mod test_utils;
 
 use crate::append_vec::{AppendVec, StoredAccountMeta};
-use crate::solana::{
-    deserialize_from, AccountsDbFields, DeserializableVersionedBank,
-    SerializableAccountStorageEntry,
-};
+use crate::filter::{AccountFilter, FilteredAccount};
+use crate::manifest::SnapshotManifest;
+use crate::status_cache::SlotStatuses;
 
 const SNAPSHOTS_DIR: &str = "snapshots";
+const VERSION_FILE: &str = "version";
 
 #[derive(Error, Debug)]
 pub enum SnapshotError {
@@ -31,18 +60,173 @@ pub enum SnapshotError {
     BincodeError(#[from] bincode::Error),
     #[error("Missing status cache")]
     NoStatusCache,
+    #[error("Unsupported snapshot version {0}")]
+    UnsupportedSnapshotVersion(String),
+    #[error("Failed to deserialize version {version} snapshot manifest: {source}")]
+    ManifestDeserialize {
+        version: SnapshotVersion,
+        source: bincode::Error,
+    },
     #[error("No snapshot manifest file found")]
     NoSnapshotManifest,
     #[error("Unexpected AppendVec")]
     UnexpectedAppendVec,
+    #[error(
+        "AppendVec {slot}.{id} holds {actual} bytes, but the snapshot manifest lists {expected}"
+    )]
+    AppendVecLengthMismatch {
+        slot: u64,
+        id: u64,
+        expected: usize,
+        actual: u64,
+    },
+    #[error("{0} AppendVecs listed in the snapshot manifest are missing")]
+    MissingAppendVecs(usize),
+    #[error("Unpacking only supports filters on account pubkeys")]
+    UnsupportedUnpackFilter,
+    #[error("Corrupt AppendVec {slot}.{id} at offset {offset}: {reason}")]
+    CorruptAppendVec {
+        slot: u64,
+        id: u64,
+        offset: usize,
+        reason: CorruptionReason,
+    },
+    #[error("{0}")]
+    AccountHashMismatch(Box<verify::AccountHashMismatch>),
+    #[error("Incremental snapshot at slot {incremental_slot} does not apply on top of full snapshot at slot {full_slot}")]
+    IncrementalSnapshotMismatch {
+        full_slot: u64,
+        incremental_slot: u64,
+    },
+    #[error("Corrupt account index: {0}")]
+    CorruptAccountIndex(&'static str),
+    #[error(
+        "Account index was built for slot {index_slot}, but snapshot is at slot {snapshot_slot}"
+    )]
+    AccountIndexMismatch { index_slot: u64, snapshot_slot: u64 },
+    #[error("Accounts must be written in ascending pubkey order")]
+    UnsortedAccounts,
+    #[error("Accounts do not fit into an AppendVec")]
+    AppendVecTooLarge,
+    #[error("Checkpoint does not match this run: {0}")]
+    CheckpointMismatch(String),
+    #[cfg(feature = "http")]
+    #[error("HTTP request failed: {0}")]
+    HttpError(#[from] reqwest::Error),
+    #[error("Snapshot at {url} changed while streaming: {reason}")]
+    SourceChanged { url: String, reason: String },
+    #[error("S3 request failed: {0}")]
+    S3Error(String),
 }
 
 pub type Result<T> = std::result::Result<T, SnapshotError>;
 
+/// Why an AppendVec record could not be parsed.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorruptionReason {
+    #[error("record is not aligned")]
+    Misaligned,
+    #[error("account meta extends past the end of the AppendVec")]
+    MetaOutOfBounds,
+    #[error("account hash extends past the end of the AppendVec")]
+    HashOutOfBounds,
+    #[error("data_len overflows the AppendVec")]
+    DataLenOverflow,
+}
+
+/// What to do with corrupt AppendVec records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CorruptionPolicy {
+    /// Fail with `SnapshotError::CorruptAppendVec`.
+    #[default]
+    Strict,
+    /// Log the corruption and skip the rest of the AppendVec.
+    Lenient,
+}
+
+impl CorruptionPolicy {
+    /// Swallows corruption errors under the lenient policy.
+    ///
+    /// All other errors are returned as is.
+    pub fn check(self, err: SnapshotError) -> Result<()> {
+        match (self, err) {
+            (Self::Lenient, err @ SnapshotError::CorruptAppendVec { .. }) => {
+                warn!("Skipping rest of AppendVec: {}", err);
+                Ok(())
+            }
+            (_, err) => Err(err),
+        }
+    }
+}
+
 pub type AppendVecIterator<'a> = Box<dyn Iterator<Item = Result<AppendVec>> + 'a>;
 
+/// Receives each stored account, along with the slot and id of the AppendVec holding it.
+pub type AccountCallback<'a> = dyn FnMut(u64, u64, &StoredAccountMeta) -> Result<()> + 'a;
+
+/// Receives each stored account seen by a filtered walk, along with the slot and id of its AppendVec.
+pub type FilteredAccountCallback<'a> = dyn FnMut(u64, u64, FilteredAccount) -> Result<()> + 'a;
+
 pub trait SnapshotExtractor: Sized {
     fn iter(&mut self) -> AppendVecIterator<'_>;
+    fn manifest(&self) -> &SnapshotManifest;
+
+    /// Decodes the status cache, or returns `None` if it has not been read yet.
+    ///
+    /// Archives may store the status cache after the AppendVecs,
+    /// in which case it only becomes available once all accounts have been read.
+    fn status_cache(&self) -> Result<Option<Vec<SlotStatuses>>>;
+
+    /// Leaves out the AppendVecs with the given slot and id from subsequent walks,
+    /// e.g. those already processed by an interrupted run.
+    fn skip_append_vecs(&mut self, append_vecs: HashSet<(u64, u64)>);
+
+    /// Calls `f` for every stored account in every AppendVec.
+    fn for_each_account(
+        &mut self,
+        policy: CorruptionPolicy,
+        f: &mut AccountCallback<'_>,
+    ) -> Result<()> {
+        self.for_each_filtered_account(policy, &AccountFilter::All, &mut |slot, id, account| {
+            match account {
+                FilteredAccount::Matched(account) => f(slot, id, &account),
+                FilteredAccount::Rejected(_) => Ok(()),
+            }
+        })
+    }
+
+    /// Calls `f` for every stored account in every AppendVec,
+    /// except those that `filter` rejects by their pubkey alone.
+    ///
+    /// Extractors reading from a stream override this to parse accounts
+    /// without buffering whole AppendVecs in memory.
+    fn for_each_filtered_account(
+        &mut self,
+        policy: CorruptionPolicy,
+        filter: &AccountFilter,
+        f: &mut FilteredAccountCallback<'_>,
+    ) -> Result<()> {
+        for append_vec in self.iter() {
+            let append_vec = append_vec?;
+            let mut offset = 0usize;
+            loop {
+                match append_vec.try_get_account(offset) {
+                    Ok(Some((account, next_offset))) => {
+                        if let Some(account) = filter.apply(account) {
+                            f(append_vec.slot(), append_vec.id(), account)?;
+                        }
+                        offset = next_offset;
+                    }
+                    Ok(None) => break,
+                    Err(err) => {
+                        policy.check(err)?;
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 fn parse_append_vec_name(name: &OsStr) -> Option<(u64, u64)> {
@@ -56,40 +240,72 @@ fn parse_append_vec_name(name: &OsStr) -> Option<(u64, u64)> {
     }
 }
 
-pub fn append_vec_iter(append_vec: Rc<AppendVec>) -> impl Iterator<Item = StoredAccountMetaHandle> {
+/// Returns a handle to each account in the AppendVec.
+///
+/// Pass an `Arc<AppendVec>` to get handles that can be sent to other threads.
+pub fn append_vec_iter<P>(
+    append_vec: P,
+    policy: CorruptionPolicy,
+) -> Result<impl Iterator<Item = StoredAccountMetaHandle<P>>>
+where
+    P: Deref<Target = AppendVec> + Clone,
+{
     let mut offsets = Vec::<usize>::new();
     let mut offset = 0usize;
     loop {
-        match append_vec.get_account(offset) {
-            None => break,
-            Some((_, next_offset)) => {
+        match append_vec.try_get_account(offset) {
+            Ok(None) => break,
+            Ok(Some((_, next_offset))) => {
                 offsets.push(offset);
                 offset = next_offset;
             }
+            Err(err) => {
+                policy.check(err)?;
+                break;
+            }
         }
     }
-    let append_vec = Rc::clone(&append_vec);
-    offsets
+    Ok(offsets
         .into_iter()
-        .map(move |offset| StoredAccountMetaHandle::new(Rc::clone(&append_vec), offset))
+        .map(move |offset| StoredAccountMetaHandle::new(append_vec.clone(), offset)))
 }
 
-pub struct StoredAccountMetaHandle {
-    append_vec: Rc<AppendVec>,
+/// Reference to an account stored in a shared AppendVec.
+#[derive(Clone)]
+pub struct StoredAccountMetaHandle<P = Rc<AppendVec>> {
+    append_vec: P,
     offset: usize,
 }
 
-impl StoredAccountMetaHandle {
-    pub fn new(append_vec: Rc<AppendVec>, offset: usize) -> StoredAccountMetaHandle {
+/// Account handle that is `Send + Sync`, for fanning out accounts to a thread pool.
+pub type SharedStoredAccountMetaHandle = StoredAccountMetaHandle<Arc<AppendVec>>;
+
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<SharedStoredAccountMetaHandle>();
+};
+
+impl<P: Deref<Target = AppendVec>> StoredAccountMetaHandle<P> {
+    pub fn new(append_vec: P, offset: usize) -> StoredAccountMetaHandle<P> {
         Self { append_vec, offset }
     }
 
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn append_vec(&self) -> &AppendVec {
+        &self.append_vec
+    }
+
     pub fn access(&self) -> Option<StoredAccountMeta<'_>> {
         Some(self.append_vec.get_account(self.offset)?.0)
     }
 }
 
+/// Reports progress of reading a snapshot file, such as a manifest or a whole (compressed) archive.
 pub trait ReadProgressTracking {
+    /// Wraps `rd`, which reads `file_len` bytes from `path`, or an unknown number if `file_len` is zero.
     fn new_read_progress_tracker(
         &self,
         path: &Path,
@@ -0,0 +1,183 @@
+use crate::solana::{
+    deserialize_from, serialize_into, AccountsDbFields, DeserializableVersionedBank,
+    ExtraFieldsToDeserialize, SerializableAccountStorageEntry,
+};
+use crate::{Result, SnapshotError};
+use log::info;
+use solana_runtime::rent_collector::RentCollector;
+use solana_runtime::snapshot_utils::SnapshotVersion;
+use solana_sdk::clock::{Epoch, Slot};
+use solana_sdk::epoch_schedule::EpochSchedule;
+use solana_sdk::fee_calculator::FeeRateGovernor;
+use solana_sdk::hash::{hashv, Hash};
+use solana_sdk::inflation::Inflation;
+use std::io::{Read, Write};
+use std::str::FromStr;
+use std::time::Instant;
+
+/// Contents of the `snapshots/<slot>/<slot>` manifest file.
+#[derive(Clone)]
+pub struct SnapshotManifest {
+    pub version: SnapshotVersion,
+    pub bank: DeserializableVersionedBank,
+    pub accounts_db_fields: AccountsDbFields<SerializableAccountStorageEntry>,
+    pub extra_fields: ExtraFieldsToDeserialize,
+}
+
+/// Parses the contents of the snapshot `version` file.
+pub fn parse_snapshot_version(contents: &str) -> Result<SnapshotVersion> {
+    let contents = contents.trim();
+    SnapshotVersion::from_str(contents)
+        .map_err(|_| SnapshotError::UnsupportedSnapshotVersion(contents.to_string()))
+}
+
+impl SnapshotManifest {
+    /// Reads a manifest using the layout of the given snapshot version.
+    pub fn read_from<R: Read>(rd: R, version: SnapshotVersion) -> Result<Self> {
+        match version {
+            SnapshotVersion::V1_2_0 => Self::read_v1_2_0(rd),
+        }
+        .map_err(|source| SnapshotError::ManifestDeserialize { version, source })
+    }
+
+    /// Solana 1.x and Agave manifests share this layout.
+    /// Newer validators append extra fields after the accounts DB fields.
+    fn read_v1_2_0<R: Read>(mut rd: R) -> bincode::Result<Self> {
+        let pre_unpack = Instant::now();
+        let bank: DeserializableVersionedBank = deserialize_from(&mut rd)?;
+        let versioned_bank_post_time = Instant::now();
+
+        let accounts_db_fields: AccountsDbFields<SerializableAccountStorageEntry> =
+            deserialize_from(&mut rd)?;
+        let accounts_db_fields_post_time = Instant::now();
+
+        let extra_fields: ExtraFieldsToDeserialize = deserialize_from(&mut rd)?;
+
+        info!(
+            "Read bank fields in {:?}",
+            versioned_bank_post_time - pre_unpack
+        );
+        info!(
+            "Read accounts DB fields in {:?}",
+            accounts_db_fields_post_time - versioned_bank_post_time
+        );
+
+        Ok(Self {
+            version: SnapshotVersion::V1_2_0,
+            bank,
+            accounts_db_fields,
+            extra_fields,
+        })
+    }
+
+    /// Writes the manifest in the layout of its snapshot version.
+    ///
+    /// Validators reject manifests with trailing bytes they do not expect,
+    /// so extra fields are only written up to the last one that is set.
+    pub fn write_to<W: Write>(&self, mut wr: W) -> Result<()> {
+        match self.version {
+            SnapshotVersion::V1_2_0 => {
+                serialize_into(&mut wr, &self.bank)?;
+                serialize_into(&mut wr, &self.accounts_db_fields)?;
+
+                let extra = &self.extra_fields;
+                let versioned_epoch_stakes = !extra.versioned_epoch_stakes.is_empty();
+                let epoch_accounts_hash =
+                    versioned_epoch_stakes || extra.epoch_accounts_hash.is_some();
+                let incremental_snapshot_persistence =
+                    epoch_accounts_hash || extra.incremental_snapshot_persistence.is_some();
+                serialize_into(&mut wr, &extra.lamports_per_signature)?;
+                if incremental_snapshot_persistence {
+                    serialize_into(&mut wr, &extra.incremental_snapshot_persistence)?;
+                }
+                if epoch_accounts_hash {
+                    serialize_into(&mut wr, &extra.epoch_accounts_hash)?;
+                }
+                if versioned_epoch_stakes {
+                    serialize_into(&mut wr, &extra.versioned_epoch_stakes)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn slot(&self) -> Slot {
+        self.bank.slot
+    }
+
+    pub fn epoch(&self) -> Epoch {
+        self.bank.epoch
+    }
+
+    pub fn block_height(&self) -> u64 {
+        self.bank.block_height
+    }
+
+    /// Hash of the bank at the snapshot slot.
+    pub fn bank_hash(&self) -> &Hash {
+        &self.bank.hash
+    }
+
+    pub fn parent_hash(&self) -> &Hash {
+        &self.bank.parent_hash
+    }
+
+    pub fn parent_slot(&self) -> Slot {
+        self.bank.parent_slot
+    }
+
+    /// Total number of lamports in existence.
+    pub fn capitalization(&self) -> u64 {
+        self.bank.capitalization
+    }
+
+    pub fn fee_rate_governor(&self) -> &FeeRateGovernor {
+        &self.bank.fee_rate_governor
+    }
+
+    pub fn epoch_schedule(&self) -> &EpochSchedule {
+        &self.bank.epoch_schedule
+    }
+
+    pub fn inflation(&self) -> &Inflation {
+        &self.bank.inflation
+    }
+
+    pub fn rent_collector(&self) -> &RentCollector {
+        &self.bank.rent_collector
+    }
+
+    /// Hash of all accounts at the snapshot slot, as computed by the validator.
+    pub fn accounts_hash(&self) -> &Hash {
+        &self.accounts_db_fields.3.snapshot_hash
+    }
+
+    /// Hash of all accounts at the slot when the epoch accounts hash was calculated, if any.
+    pub fn epoch_accounts_hash(&self) -> Option<&Hash> {
+        self.extra_fields.epoch_accounts_hash.as_ref()
+    }
+
+    /// Hash in the name of the snapshot archive: the accounts hash,
+    /// combined with the epoch accounts hash if the manifest has one.
+    pub fn archive_hash(&self) -> Hash {
+        match self.epoch_accounts_hash() {
+            Some(epoch_accounts_hash) => {
+                hashv(&[self.accounts_hash().as_ref(), epoch_accounts_hash.as_ref()])
+            }
+            None => *self.accounts_hash(),
+        }
+    }
+
+    /// Returns the storage entry the manifest lists for the given AppendVec, if any.
+    pub fn append_vec_entry(
+        &self,
+        slot: Slot,
+        id: u64,
+    ) -> Option<&SerializableAccountStorageEntry> {
+        self.accounts_db_fields
+            .0
+            .get(&slot)?
+            .iter()
+            .find(|entry| entry.id == (id as usize))
+    }
+}
@@ -0,0 +1,161 @@
+use crate::archived::ArchiveSnapshotExtractor;
+use crate::manifest::SnapshotManifest;
+use crate::status_cache::SlotStatuses;
+use crate::{AppendVec, Result, SnapshotError, SnapshotExtractor};
+use futures_core::Stream;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::AsyncRead;
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::io::SyncIoBridge;
+
+/// Number of decoded AppendVecs buffered ahead of the consumer.
+const CHANNEL_CAPACITY: usize = 4;
+
+enum Message {
+    AppendVec(Result<AppendVec>),
+    /// Sent once all AppendVecs have been read.
+    Done(Result<Option<Vec<SlotStatuses>>>),
+}
+
+/// Extracts account data from a snapshot archive read from an async stream.
+///
+/// Decoding runs on tokio's blocking thread pool using `ArchiveSnapshotExtractor`,
+/// so the same archive formats are supported and the same manifest parsing applies.
+/// AppendVecs are yielded as a `Stream` and can be walked with `append_vec_iter`.
+/// Dropping the extractor stops decoding.
+pub struct AsyncArchiveSnapshotExtractor {
+    manifest: SnapshotManifest,
+    rx: mpsc::Receiver<Message>,
+    status_cache: Option<Vec<SlotStatuses>>,
+    done: bool,
+}
+
+impl AsyncArchiveSnapshotExtractor {
+    /// Reads the archive up to the snapshot manifest.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub async fn from_reader<Source>(source: Source) -> Result<Self>
+    where
+        Source: AsyncRead + Send + Unpin + 'static,
+    {
+        Self::from_reader_inner(source, None, HashSet::new()).await
+    }
+
+    /// Like `from_reader`, but accepts archives where AppendVecs precede the snapshot manifest.
+    ///
+    /// See `ArchiveSnapshotExtractor::from_reader_spilling`.
+    pub async fn from_reader_spilling<Source>(source: Source, spill_dir: &Path) -> Result<Self>
+    where
+        Source: AsyncRead + Send + Unpin + 'static,
+    {
+        Self::from_reader_inner(source, Some(spill_dir.to_path_buf()), HashSet::new()).await
+    }
+
+    /// Like `from_reader`, but leaves out the AppendVecs with the given slot and id.
+    pub async fn from_reader_skipping<Source>(
+        source: Source,
+        skip: HashSet<(u64, u64)>,
+    ) -> Result<Self>
+    where
+        Source: AsyncRead + Send + Unpin + 'static,
+    {
+        Self::from_reader_inner(source, None, skip).await
+    }
+
+    async fn from_reader_inner<Source>(
+        source: Source,
+        spill_dir: Option<PathBuf>,
+        skip: HashSet<(u64, u64)>,
+    ) -> Result<Self>
+    where
+        Source: AsyncRead + Send + Unpin + 'static,
+    {
+        let source = SyncIoBridge::new(source);
+        let (manifest_tx, manifest_rx) = oneshot::channel();
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        tokio::task::spawn_blocking(move || {
+            let extractor = match spill_dir {
+                Some(spill_dir) => {
+                    ArchiveSnapshotExtractor::from_reader_spilling(source, &spill_dir)
+                }
+                None => ArchiveSnapshotExtractor::from_reader(source),
+            };
+            let mut extractor = match extractor {
+                Ok(extractor) => {
+                    if manifest_tx.send(Ok(extractor.manifest().clone())).is_err() {
+                        return;
+                    }
+                    extractor
+                }
+                Err(err) => {
+                    let _ = manifest_tx.send(Err(err));
+                    return;
+                }
+            };
+            extractor.skip_append_vecs(skip);
+            for append_vec in extractor.iter() {
+                if tx.blocking_send(Message::AppendVec(append_vec)).is_err() {
+                    return;
+                }
+            }
+            let _ = tx.blocking_send(Message::Done(extractor.status_cache()));
+        });
+        let manifest = manifest_rx.await.map_err(|_| extractor_exited())??;
+        Ok(Self {
+            manifest,
+            rx,
+            status_cache: None,
+            done: false,
+        })
+    }
+
+    pub fn manifest(&self) -> &SnapshotManifest {
+        &self.manifest
+    }
+
+    /// Returns the decoded status cache, or `None` if it has not been read yet.
+    ///
+    /// Archives may store the status cache after the AppendVecs,
+    /// in which case it only becomes available once the stream has ended.
+    pub fn status_cache(&self) -> Option<&[SlotStatuses]> {
+        self.status_cache.as_deref()
+    }
+}
+
+impl Stream for AsyncArchiveSnapshotExtractor {
+    type Item = Result<AppendVec>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        match self.rx.poll_recv(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Some(Message::AppendVec(append_vec))) => Poll::Ready(Some(append_vec)),
+            Poll::Ready(Some(Message::Done(status_cache))) => {
+                self.done = true;
+                match status_cache {
+                    Ok(status_cache) => {
+                        self.status_cache = status_cache;
+                        Poll::Ready(None)
+                    }
+                    Err(err) => Poll::Ready(Some(Err(err))),
+                }
+            }
+            // The decoding task panicked before reaching the end of the archive.
+            Poll::Ready(None) => {
+                self.done = true;
+                Poll::Ready(Some(Err(extractor_exited())))
+            }
+        }
+    }
+}
+
+fn extractor_exited() -> SnapshotError {
+    SnapshotError::IOError(std::io::Error::other(
+        "archive extractor exited unexpectedly",
+    ))
+}
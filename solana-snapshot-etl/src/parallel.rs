@@ -1,7 +1,12 @@
 use crate::{AppendVec, AppendVecIterator};
-use crossbeam::sync::WaitGroup;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 
 pub type GenericResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+/// Result of a consumer, whose errors are passed back to the producer thread.
+pub type ConsumerResult<T> = std::result::Result<T, ConsumerError>;
+pub type ConsumerError = Box<dyn std::error::Error + Send + Sync>;
 
 pub trait AppendVecConsumerFactory {
     type Consumer: AppendVecConsumer + Send + 'static;
@@ -9,41 +14,325 @@ pub trait AppendVecConsumerFactory {
 }
 
 pub trait AppendVecConsumer {
-    fn on_append_vec(&mut self, append_vec: AppendVec) -> GenericResult<()>;
+    fn on_append_vec(&mut self, append_vec: AppendVec) -> ConsumerResult<()>;
+
+    /// Called once the consumer receives no more AppendVecs, e.g. to flush or commit its output.
+    ///
+    /// Also called when the iteration was cancelled or this consumer failed,
+    /// but not when it panicked.
+    fn on_finish(&mut self) -> ConsumerResult<()> {
+        Ok(())
+    }
+}
+
+/// Errors of the consumers in `par_iter_append_vecs`, in the order they occurred.
+#[derive(Debug)]
+pub struct ConsumerErrors(pub Vec<ConsumerError>);
+
+impl fmt::Display for ConsumerErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} AppendVec consumer(s) failed", self.0.len())?;
+        for (i, err) in self.0.iter().enumerate() {
+            f.write_str(if i == 0 { ": " } else { "; " })?;
+            write!(f, "{}", err)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConsumerErrors {}
+
+/// State shared by the producer and consumer threads.
+struct Shared {
+    cancelled: AtomicBool,
+    /// Bytes of AppendVecs sent but not yet consumed.
+    in_flight: Mutex<usize>,
+    freed: Condvar,
+    errors: Mutex<Vec<ConsumerError>>,
+}
+
+impl Shared {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+        // Take the lock so that a producer about to wait cannot miss the wakeup.
+        let _in_flight = self.in_flight.lock().unwrap();
+        self.freed.notify_all();
+    }
+
+    fn fail(&self, err: ConsumerError) {
+        self.errors.lock().unwrap().push(err);
+        self.cancel();
+    }
+}
+
+/// Share of the memory budget held by an AppendVec until it has been consumed.
+struct Permit {
+    shared: Arc<Shared>,
+    bytes: usize,
+}
+
+impl Permit {
+    /// Waits until `bytes` fit into the budget, or returns `None` if cancelled meanwhile.
+    ///
+    /// AppendVecs larger than the budget are sent once nothing else is in flight.
+    fn acquire(shared: &Arc<Shared>, bytes: usize, mem_budget: usize) -> Option<Self> {
+        let bytes = bytes.min(mem_budget);
+        let mut in_flight = shared.in_flight.lock().unwrap();
+        while *in_flight + bytes > mem_budget && !shared.is_cancelled() {
+            in_flight = shared.freed.wait(in_flight).unwrap();
+        }
+        if shared.is_cancelled() {
+            return None;
+        }
+        *in_flight += bytes;
+        Some(Self {
+            shared: Arc::clone(shared),
+            bytes,
+        })
+    }
 }
 
+impl Drop for Permit {
+    fn drop(&mut self) {
+        *self.shared.in_flight.lock().unwrap() -= self.bytes;
+        self.shared.freed.notify_all();
+    }
+}
+
+/// Cancels the iteration if a consumer thread panics.
+struct CancelOnPanic(Arc<Shared>);
+
+impl Drop for CancelOnPanic {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.0.cancel();
+        }
+    }
+}
+
+/// Feeds AppendVecs to `num_threads` consumers running on their own threads.
+///
+/// At most `mem_budget` bytes of AppendVecs are read ahead of the consumers.
+/// The first error of the iterator or a consumer cancels the iteration:
+/// no further AppendVecs are read or consumed, and every consumer is finished.
+/// Consumer errors are returned as `ConsumerErrors`, otherwise the error of the iterator is returned.
 pub fn par_iter_append_vecs<A>(
     iterator: AppendVecIterator<'_>,
     consumers: &mut A,
     num_threads: usize,
+    mem_budget: usize,
 ) -> GenericResult<()>
 where
     A: AppendVecConsumerFactory,
 {
-    let (tx, rx) = crossbeam::channel::bounded::<AppendVec>(num_threads);
+    let (tx, rx) = crossbeam::channel::unbounded::<(AppendVec, Permit)>();
+    let shared = Arc::new(Shared {
+        cancelled: AtomicBool::new(false),
+        in_flight: Mutex::new(0),
+        freed: Condvar::new(),
+        errors: Mutex::new(Vec::new()),
+    });
 
-    let wg = WaitGroup::new();
     let mut consumer_vec = Vec::with_capacity(num_threads);
     for _ in 0..num_threads {
         consumer_vec.push(consumers.new_consumer()?);
     }
 
+    let mut handles = Vec::with_capacity(num_threads);
     for mut consumer in consumer_vec {
         let rx = rx.clone();
-        let wg = wg.clone();
-        std::thread::spawn(move || {
-            while let Ok(item) = rx.recv() {
-                consumer.on_append_vec(item).expect("insert failed")
+        let shared = Arc::clone(&shared);
+        handles.push(std::thread::spawn(move || {
+            let _guard = CancelOnPanic(Arc::clone(&shared));
+            while let Ok((append_vec, _permit)) = rx.recv() {
+                if shared.is_cancelled() {
+                    break;
+                }
+                if let Err(err) = consumer.on_append_vec(append_vec) {
+                    shared.fail(err);
+                    break;
+                }
             }
-            drop(wg);
-        });
+            // Stop receiving before finishing, so the producer notices once no consumer is left.
+            drop(rx);
+            if let Err(err) = consumer.on_finish() {
+                shared.fail(err);
+            }
+        }));
     }
+    drop(rx);
 
+    let mut producer_err = None;
     for append_vec in iterator {
-        let append_vec = append_vec?;
-        tx.send(append_vec).expect("failed to send AppendVec");
+        if shared.is_cancelled() {
+            break;
+        }
+        let append_vec = match append_vec {
+            Ok(append_vec) => append_vec,
+            Err(err) => {
+                producer_err = Some(err);
+                shared.cancel();
+                break;
+            }
+        };
+        let permit = match Permit::acquire(&shared, append_vec.len(), mem_budget) {
+            Some(permit) => permit,
+            None => break,
+        };
+        if tx.send((append_vec, permit)).is_err() {
+            break;
+        }
     }
     drop(tx);
-    wg.wait();
-    Ok(())
+
+    for handle in handles {
+        if handle.join().is_err() {
+            shared
+                .errors
+                .lock()
+                .unwrap()
+                .push("AppendVec consumer panicked".into());
+        }
+    }
+    let errors = std::mem::take(&mut *shared.errors.lock().unwrap());
+    if !errors.is_empty() {
+        return Err(Box::new(ConsumerErrors(errors)));
+    }
+    match producer_err {
+        Some(err) => Err(Box::new(err)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{append_vec, pubkey, TestAccount};
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    const APPEND_VECS: u64 = 100;
+
+    /// Tracks the bytes produced and consumed, and the consumers finished.
+    #[derive(Default)]
+    struct Counters {
+        produced_bytes: AtomicUsize,
+        consumed: AtomicUsize,
+        consumed_bytes: AtomicUsize,
+        max_in_flight: AtomicUsize,
+        finished: AtomicUsize,
+    }
+
+    struct CountingConsumer {
+        counters: Arc<Counters>,
+        fail: bool,
+    }
+
+    impl AppendVecConsumer for CountingConsumer {
+        fn on_append_vec(&mut self, append_vec: AppendVec) -> ConsumerResult<()> {
+            let counters = &self.counters;
+            let in_flight = counters.produced_bytes.load(Ordering::SeqCst)
+                - counters.consumed_bytes.load(Ordering::SeqCst);
+            counters
+                .max_in_flight
+                .fetch_max(in_flight, Ordering::SeqCst);
+            if self.fail {
+                return Err(Box::new(std::io::Error::other("consumer failed")));
+            }
+            // Give the producer time to read ahead as far as the budget allows.
+            std::thread::sleep(Duration::from_millis(1));
+            counters.consumed.fetch_add(1, Ordering::SeqCst);
+            counters
+                .consumed_bytes
+                .fetch_add(append_vec.len(), Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn on_finish(&mut self) -> ConsumerResult<()> {
+            self.counters.finished.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    /// Creates counting consumers, which fail on their first AppendVec if `fail` is set.
+    struct Consumers {
+        counters: Arc<Counters>,
+        fail: bool,
+    }
+
+    impl AppendVecConsumerFactory for Consumers {
+        type Consumer = CountingConsumer;
+
+        fn new_consumer(&mut self) -> GenericResult<CountingConsumer> {
+            Ok(CountingConsumer {
+                counters: Arc::clone(&self.counters),
+                fail: self.fail,
+            })
+        }
+    }
+
+    /// Runs `num_threads` consumers over AppendVecs of the same length,
+    /// and returns the number of AppendVecs produced.
+    fn run(
+        consumers: &mut Consumers,
+        num_threads: usize,
+        mem_budget: usize,
+    ) -> (GenericResult<()>, usize) {
+        let counters = Arc::clone(&consumers.counters);
+        let produced = Arc::new(AtomicUsize::new(0));
+        let produced_count = Arc::clone(&produced);
+        let iterator = (0..APPEND_VECS).map(move |id| {
+            let account = TestAccount::new(pubkey(1), id, 10, &[0; 1000]);
+            let append_vec = append_vec(1, id, &[account]);
+            produced_count.fetch_add(1, Ordering::SeqCst);
+            counters
+                .produced_bytes
+                .fetch_add(append_vec.len(), Ordering::SeqCst);
+            Ok(append_vec)
+        });
+        let result = par_iter_append_vecs(Box::new(iterator), consumers, num_threads, mem_budget);
+        (result, produced.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn bounds_bytes_in_flight() {
+        let append_vec_len =
+            append_vec(1, 0, &[TestAccount::new(pubkey(1), 0, 10, &[0; 1000])]).len();
+        let mut consumers = Consumers {
+            counters: Arc::default(),
+            fail: false,
+        };
+        let mem_budget = 3 * append_vec_len;
+        let (result, produced) = run(&mut consumers, 4, mem_budget);
+        result.unwrap();
+        assert_eq!(produced, APPEND_VECS as usize);
+
+        let counters = &consumers.counters;
+        assert_eq!(counters.consumed.load(Ordering::SeqCst), produced);
+        assert_eq!(counters.finished.load(Ordering::SeqCst), 4);
+        // The producer may read one AppendVec ahead while it waits for the budget.
+        assert!(counters.max_in_flight.load(Ordering::SeqCst) <= mem_budget + append_vec_len);
+    }
+
+    #[test]
+    fn returns_consumer_errors_and_stops_producer() {
+        let mut consumers = Consumers {
+            counters: Arc::default(),
+            fail: true,
+        };
+        // Only one AppendVec is in flight at a time, so only one consumer fails.
+        let (result, produced) = run(&mut consumers, 4, 1);
+        let err = result.unwrap_err();
+        let errors = &err.downcast_ref::<ConsumerErrors>().unwrap().0;
+        assert_eq!(errors.len(), 1);
+        let err = errors[0].downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(err.to_string(), "consumer failed");
+        // The producer stops once it has read the AppendVec after the failed one.
+        assert!(produced <= 2);
+        assert_eq!(consumers.counters.finished.load(Ordering::SeqCst), 4);
+    }
 }
@@ -0,0 +1,382 @@
+use crate::decompress::ArchiveDecoder;
+use crossbeam::channel::{Receiver, Select, Sender};
+use solana_runtime::snapshot_utils::ArchiveFormat;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+
+/// Size of the compressed and decompressed chunks passed between threads.
+const CHUNK_SIZE: usize = 1 << 20;
+/// Number of compressed chunks buffered ahead of the decompression thread.
+const INPUT_CAPACITY: usize = 16;
+
+const ZSTD_MAGIC_NUMBER: u32 = 0xfd2f_b528;
+const ZSTD_SKIPPABLE_MAGIC_NUMBER: u32 = 0x184d_2a50;
+/// Compressed zstd frames up to this size are decompressed on the worker threads.
+const MAX_PARALLEL_FRAME_SIZE: usize = 16 << 20;
+/// Frames declaring a larger decompressed size, or none at all, are decompressed on the pipeline thread.
+const MAX_PARALLEL_CONTENT_SIZE: u64 = 256 << 20;
+
+/// Decompressed output of the pipeline thread, in stream order.
+enum Output {
+    Data(Vec<u8>),
+    /// zstd frame decompressed by a worker thread.
+    Frame(Receiver<io::Result<Vec<u8>>>),
+    Error(io::Error),
+    End,
+}
+
+/// Decompresses a snapshot archive stream on background threads.
+///
+/// The source is read on the caller's thread, which also parses the tar stream,
+/// while decompression runs on a pipeline thread.
+/// zstd archives made of multiple frames, like those written by `pzstd`,
+/// have their frames decompressed in parallel on `num_threads` worker threads.
+/// Archives with a single large frame are decompressed on the pipeline thread alone.
+/// Dropping the decoder stops the background threads.
+pub struct PipelinedDecoder<Source: Read> {
+    source: Source,
+    /// Compressed chunk read from the source, but not yet taken by the pipeline thread.
+    pending: Option<Vec<u8>>,
+    input: Option<Sender<Vec<u8>>>,
+    output: Receiver<Output>,
+    current: Cursor<Vec<u8>>,
+    done: bool,
+}
+
+impl<Source: Read> PipelinedDecoder<Source> {
+    /// Starts decompressing a stream of the given format, whose first bytes were already read into `header`.
+    pub(crate) fn new(
+        source: Source,
+        header: Vec<u8>,
+        format: ArchiveFormat,
+        num_threads: usize,
+    ) -> Self {
+        let (input_tx, input_rx) = crossbeam::channel::bounded(INPUT_CAPACITY);
+        let (output_tx, output_rx) = crossbeam::channel::bounded(2 * num_threads.max(1));
+        std::thread::spawn(move || {
+            let input = ChannelReader {
+                rx: input_rx,
+                current: Cursor::new(Vec::new()),
+            };
+            let output = match format {
+                ArchiveFormat::TarZstd => decode_zstd_frames(
+                    &mut BufReader::with_capacity(CHUNK_SIZE, input),
+                    &output_tx,
+                    num_threads.max(1),
+                ),
+                format => decode_stream(input, format, &output_tx),
+            };
+            let _ = output_tx.send(match output {
+                Ok(()) => Output::End,
+                Err(err) => Output::Error(err),
+            });
+        });
+        Self {
+            source,
+            pending: (!header.is_empty()).then_some(header),
+            input: Some(input_tx),
+            output: output_rx,
+            current: Cursor::new(Vec::new()),
+            done: false,
+        }
+    }
+
+    /// Waits for the next output, feeding the source to the pipeline thread meanwhile.
+    fn next_output(&mut self) -> io::Result<Output> {
+        loop {
+            if self.pending.is_none() && self.input.is_some() {
+                let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+                (&mut self.source)
+                    .take(CHUNK_SIZE as u64)
+                    .read_to_end(&mut chunk)?;
+                if chunk.is_empty() {
+                    // Closing the input tells the pipeline thread that the stream has ended.
+                    self.input = None;
+                } else {
+                    self.pending = Some(chunk);
+                }
+            }
+            let input = match (&self.input, &self.pending) {
+                (Some(input), Some(_)) => input.clone(),
+                _ => return self.output.recv().map_err(|_| pipeline_exited()),
+            };
+            let mut select = Select::new();
+            let send = select.send(&input);
+            let recv = select.recv(&self.output);
+            let op = select.select();
+            if op.index() == recv {
+                return op.recv(&self.output).map_err(|_| pipeline_exited());
+            }
+            debug_assert_eq!(op.index(), send);
+            if op.send(&input, self.pending.take().unwrap()).is_err() {
+                // The pipeline thread stopped reading, its output tells why.
+                self.input = None;
+            }
+        }
+    }
+}
+
+impl<Source: Read> Read for PipelinedDecoder<Source> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.current.read(buf)?;
+            if n > 0 || buf.is_empty() || self.done {
+                return Ok(n);
+            }
+            match self.next_output()? {
+                Output::Data(data) => self.current = Cursor::new(data),
+                Output::Frame(rx) => match rx.recv() {
+                    Ok(Ok(data)) => self.current = Cursor::new(data),
+                    Ok(Err(err)) => {
+                        self.done = true;
+                        return Err(err);
+                    }
+                    Err(_) => {
+                        self.done = true;
+                        return Err(pipeline_exited());
+                    }
+                },
+                Output::Error(err) => {
+                    self.done = true;
+                    return Err(err);
+                }
+                Output::End => self.done = true,
+            }
+        }
+    }
+}
+
+/// Reads the compressed chunks sent by `PipelinedDecoder`, ending once its input is closed.
+struct ChannelReader {
+    rx: Receiver<Vec<u8>>,
+    current: Cursor<Vec<u8>>,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.current.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            match self.rx.recv() {
+                Ok(chunk) => self.current = Cursor::new(chunk),
+                Err(_) => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Decompresses a stream on the pipeline thread.
+fn decode_stream<R: Read>(
+    input: R,
+    format: ArchiveFormat,
+    output: &Sender<Output>,
+) -> io::Result<()> {
+    let reader = BufReader::new(Cursor::new(Vec::new()).chain(input));
+    send_chunks(ArchiveDecoder::with_format(reader, format)?, output)
+}
+
+/// Splits a zstd stream into frames and hands them to worker threads.
+///
+/// Frame boundaries are found from the block headers, without decompressing.
+/// Frames too large to buffer, or whose decompressed size is unknown,
+/// are decompressed on the pipeline thread instead.
+fn decode_zstd_frames<R: BufRead>(
+    rd: &mut R,
+    output: &Sender<Output>,
+    num_threads: usize,
+) -> io::Result<()> {
+    let (jobs_tx, jobs_rx) =
+        crossbeam::channel::bounded::<(Vec<u8>, Sender<io::Result<Vec<u8>>>)>(num_threads);
+    for _ in 0..num_threads {
+        let jobs_rx = jobs_rx.clone();
+        std::thread::spawn(move || {
+            for (frame, result_tx) in jobs_rx {
+                let _ = result_tx.send(zstd::stream::decode_all(&frame[..]));
+            }
+        });
+    }
+
+    loop {
+        let mut frame = Vec::new();
+        match read_into(rd, &mut frame, 4)? {
+            0 => return Ok(()),
+            4 => {}
+            _ => return Err(truncated_frame()),
+        }
+        let magic = u32::from_le_bytes(frame[..4].try_into().unwrap());
+        if magic & 0xffff_fff0 == ZSTD_SKIPPABLE_MAGIC_NUMBER {
+            read_exact_into(rd, &mut frame, 4)?;
+            let len = u32::from_le_bytes(frame[4..8].try_into().unwrap());
+            io::copy(&mut (&mut *rd).take(len as u64), &mut io::sink())?;
+            continue;
+        }
+        if magic != ZSTD_MAGIC_NUMBER {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid zstd frame magic number",
+            ));
+        }
+
+        read_exact_into(rd, &mut frame, 1)?;
+        let descriptor = frame[4];
+        let content_size_flag = descriptor >> 6;
+        let single_segment = descriptor & 0x20 != 0;
+        let checksum = descriptor & 0x04 != 0;
+        let content_size_len = match content_size_flag {
+            0 => single_segment as usize,
+            1 => 2,
+            2 => 4,
+            _ => 8,
+        };
+        let header_len = !single_segment as usize
+            + [0, 1, 2, 4][(descriptor & 0x03) as usize]
+            + content_size_len;
+        read_exact_into(rd, &mut frame, header_len)?;
+        let content_size = (content_size_len > 0).then(|| {
+            let mut bytes = [0u8; 8];
+            bytes[..content_size_len].copy_from_slice(&frame[frame.len() - content_size_len..]);
+            let size = u64::from_le_bytes(bytes);
+            if content_size_len == 2 {
+                size + 256
+            } else {
+                size
+            }
+        });
+
+        let mut parallel = content_size.is_some_and(|size| size <= MAX_PARALLEL_CONTENT_SIZE);
+        while parallel {
+            read_exact_into(rd, &mut frame, 3)?;
+            let block_header = u32::from_le_bytes([
+                frame[frame.len() - 3],
+                frame[frame.len() - 2],
+                frame[frame.len() - 1],
+                0,
+            ]);
+            let last_block = block_header & 1 != 0;
+            let block_len = match (block_header >> 1) & 0x03 {
+                // RLE blocks store a single byte.
+                1 => 1,
+                3 => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "reserved zstd block type",
+                    ))
+                }
+                _ => (block_header >> 3) as usize,
+            };
+            if frame.len() + block_len > MAX_PARALLEL_FRAME_SIZE {
+                parallel = false;
+                break;
+            }
+            read_exact_into(rd, &mut frame, block_len)?;
+            if last_block {
+                if checksum {
+                    read_exact_into(rd, &mut frame, 4)?;
+                }
+                break;
+            }
+        }
+
+        if parallel {
+            let (result_tx, result_rx) = crossbeam::channel::bounded(1);
+            jobs_tx
+                .send((frame, result_tx))
+                .map_err(|_| pipeline_exited())?;
+            if output.send(Output::Frame(result_rx)).is_err() {
+                return Ok(());
+            }
+        } else {
+            // Replay the bytes read so far, and decompress up to the end of the frame.
+            let rest = Cursor::new(frame).chain(&mut *rd);
+            send_chunks(
+                zstd::stream::read::Decoder::with_buffer(rest)?.single_frame(),
+                output,
+            )?;
+        }
+    }
+}
+
+/// Sends everything read from `rd` as output chunks.
+fn send_chunks<R: Read>(mut rd: R, output: &Sender<Output>) -> io::Result<()> {
+    loop {
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+        if (&mut rd).take(CHUNK_SIZE as u64).read_to_end(&mut chunk)? == 0 {
+            return Ok(());
+        }
+        if output.send(Output::Data(chunk)).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Appends up to `len` bytes to `buf`, returning how many were read before the end of the stream.
+fn read_into<R: Read>(rd: &mut R, buf: &mut Vec<u8>, len: usize) -> io::Result<usize> {
+    rd.take(len as u64).read_to_end(buf)
+}
+
+fn read_exact_into<R: Read>(rd: &mut R, buf: &mut Vec<u8>, len: usize) -> io::Result<()> {
+    if read_into(rd, buf, len)? < len {
+        return Err(truncated_frame());
+    }
+    Ok(())
+}
+
+fn truncated_frame() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "zstd frame is truncated")
+}
+
+fn pipeline_exited() -> io::Error {
+    io::Error::other("decompression pipeline exited unexpectedly")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns `len` bytes that zstd cannot compress.
+    fn random_bytes(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_frames_in_stream_order() {
+        let parts = [
+            vec![1u8; 100_000],
+            (0..200_000u32).map(|i| (i % 7) as u8).collect(),
+            random_bytes(MAX_PARALLEL_FRAME_SIZE + (1 << 20)),
+            vec![2u8; 300],
+        ];
+        let mut stream = Vec::new();
+        // Declares its content size.
+        stream.extend(zstd::bulk::compress(&parts[0], 3).unwrap());
+        let skippable = b"not part of the output";
+        stream.extend(ZSTD_SKIPPABLE_MAGIC_NUMBER.to_le_bytes());
+        stream.extend((skippable.len() as u32).to_le_bytes());
+        stream.extend(skippable);
+        // Streamed frames do not declare their content size.
+        let mut encoder = zstd::stream::Encoder::new(Vec::new(), 3).unwrap();
+        io::Write::write_all(&mut encoder, &parts[1]).unwrap();
+        stream.extend(encoder.finish().unwrap());
+        // Too large to buffer for a worker.
+        let large = zstd::bulk::compress(&parts[2], 1).unwrap();
+        assert!(large.len() > MAX_PARALLEL_FRAME_SIZE);
+        stream.extend(large);
+        stream.extend(zstd::bulk::compress(&parts[3], 3).unwrap());
+
+        let mut decoder =
+            PipelinedDecoder::new(Cursor::new(stream), Vec::new(), ArchiveFormat::TarZstd, 4);
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(output.len(), parts.iter().map(Vec::len).sum::<usize>());
+        assert!(output == parts.concat());
+    }
+}
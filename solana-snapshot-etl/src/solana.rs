@@ -16,14 +16,14 @@
 
 use bincode::Options;
 use serde::de::DeserializeOwned;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use solana_runtime::accounts_db::BankHashInfo;
 use solana_runtime::ancestors::AncestorsForSerialization;
 use solana_runtime::append_vec::StoredMetaWriteVersion;
 use solana_runtime::blockhash_queue::BlockhashQueue;
-use solana_runtime::epoch_stakes::EpochStakes;
+use solana_runtime::epoch_stakes::{EpochAuthorizedVoters, NodeIdToVoteAccounts};
 use solana_runtime::rent_collector::RentCollector;
-use solana_runtime::stakes::Stakes;
+use solana_sdk::account::Account;
 use solana_sdk::clock::{Epoch, UnixTimestamp};
 use solana_sdk::deserialize_utils::default_on_eof;
 use solana_sdk::epoch_schedule::EpochSchedule;
@@ -33,9 +33,10 @@ use solana_sdk::hash::Hash;
 use solana_sdk::inflation::Inflation;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::slot_history::Slot;
-use solana_sdk::stake::state::Delegation;
+use solana_sdk::stake::state::{Delegation, Stake};
+use solana_sdk::stake_history::StakeHistory;
 use std::collections::{HashMap, HashSet};
-use std::io::Read;
+use std::io::{Read, Write};
 
 const MAX_STREAM_SIZE: u64 = 32 * 1024 * 1024 * 1024;
 
@@ -51,14 +52,24 @@ where
         .deserialize_from::<R, T>(reader)
 }
 
-#[derive(Default, PartialEq, Eq, Debug, Deserialize)]
+pub fn serialize_into<W, T>(writer: W, value: &T) -> bincode::Result<()>
+where
+    W: Write,
+    T: Serialize + ?Sized,
+{
+    bincode::options()
+        .with_fixint_encoding()
+        .serialize_into(writer, value)
+}
+
+#[derive(Clone, Default, PartialEq, Eq, Debug, Deserialize, Serialize)]
 struct UnusedAccounts {
     unused1: HashSet<Pubkey>,
     unused2: HashSet<Pubkey>,
     unused3: HashMap<Pubkey, u64>,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 #[allow(dead_code)]
 pub struct DeserializableVersionedBank {
     pub blockhash_queue: BlockhashQueue,
@@ -96,7 +107,27 @@ pub struct DeserializableVersionedBank {
     pub is_delta: bool,
 }
 
-#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+/// Serialized form of `solana_runtime::stakes::Stakes`, with public fields.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Stakes<T> {
+    /// vote accounts, with the stake delegated to each
+    pub vote_accounts: HashMap<Pubkey, (u64, Account)>,
+    pub stake_delegations: HashMap<Pubkey, T>,
+    pub unused: u64,
+    pub epoch: Epoch,
+    pub stake_history: StakeHistory,
+}
+
+/// Serialized form of `solana_runtime::epoch_stakes::EpochStakes`, with public fields.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct EpochStakes {
+    pub stakes: Stakes<Delegation>,
+    pub total_stake: u64,
+    pub node_id_to_vote_accounts: NodeIdToVoteAccounts,
+    pub epoch_authorized_voters: EpochAuthorizedVoters,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
 pub struct AccountsDbFields<T>(
     pub HashMap<Slot, Vec<T>>,
     pub StoredMetaWriteVersion,
@@ -112,8 +143,44 @@ pub struct AccountsDbFields<T>(
 
 pub type SerializedAppendVecId = usize;
 
-#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
 pub struct SerializableAccountStorageEntry {
     pub id: SerializedAppendVecId,
     pub accounts_current_len: usize,
 }
+
+/// Fields that newer validators append to the manifest after `AccountsDbFields`.
+///
+/// Older manifests end early, so every field defaults on EOF.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct ExtraFieldsToDeserialize {
+    #[serde(deserialize_with = "default_on_eof")]
+    pub lamports_per_signature: u64,
+    #[serde(deserialize_with = "default_on_eof")]
+    pub incremental_snapshot_persistence: Option<BankIncrementalSnapshotPersistence>,
+    #[serde(deserialize_with = "default_on_eof")]
+    pub epoch_accounts_hash: Option<Hash>,
+    #[serde(deserialize_with = "default_on_eof")]
+    pub versioned_epoch_stakes: HashMap<Epoch, VersionedEpochStakes>,
+}
+
+/// Accounts hashes of the full snapshot an incremental snapshot is based on.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct BankIncrementalSnapshotPersistence {
+    pub full_slot: Slot,
+    pub full_hash: Hash,
+    pub full_capitalization: u64,
+    pub incremental_hash: Hash,
+    pub incremental_capitalization: u64,
+}
+
+/// Epoch stakes as written by newer validators, with full stakes instead of delegations.
+#[derive(Clone, Deserialize, Serialize)]
+pub enum VersionedEpochStakes {
+    Current {
+        stakes: Stakes<Stake>,
+        total_stake: u64,
+        node_id_to_vote_accounts: NodeIdToVoteAccounts,
+        epoch_authorized_voters: EpochAuthorizedVoters,
+    },
+}
@@ -0,0 +1,131 @@
+use crate::manifest::SnapshotManifest;
+use crate::solana::{EpochStakes, Stakes, VersionedEpochStakes};
+use solana_sdk::clock::Epoch;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{BTreeMap, HashMap};
+
+/// Stake delegated from a stake account to a vote account.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StakeDelegation {
+    pub stake_pubkey: Pubkey,
+    pub voter_pubkey: Pubkey,
+    pub stake: u64,
+    pub activation_epoch: Epoch,
+    pub deactivation_epoch: Epoch,
+}
+
+/// Vote account along with the validator identity it votes for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VoteAccountStake {
+    pub vote_pubkey: Pubkey,
+    /// `None` if the vote account data is too short to hold a vote state.
+    pub node_pubkey: Option<Pubkey>,
+    pub stake: u64,
+    pub lamports: u64,
+}
+
+/// Stakes frozen for leader schedule and vote weight calculations of an epoch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EpochStakesSummary {
+    pub epoch: Epoch,
+    pub total_stake: u64,
+    pub vote_accounts: Vec<VoteAccountStake>,
+    /// Authorized voter of each vote account during the epoch.
+    pub authorized_voters: Vec<(Pubkey, Pubkey)>,
+}
+
+/// Returns the validator identity stored in a vote account.
+///
+/// Every vote state version starts with a `u32` version tag followed by the node pubkey.
+pub fn vote_node_pubkey(data: &[u8]) -> Option<Pubkey> {
+    data.get(4..36).map(Pubkey::new)
+}
+
+fn vote_accounts<T>(stakes: &Stakes<T>) -> Vec<VoteAccountStake> {
+    let mut vote_accounts = stakes
+        .vote_accounts
+        .iter()
+        .map(|(vote_pubkey, (stake, account))| VoteAccountStake {
+            vote_pubkey: *vote_pubkey,
+            node_pubkey: vote_node_pubkey(&account.data),
+            stake: *stake,
+            lamports: account.lamports,
+        })
+        .collect::<Vec<_>>();
+    vote_accounts.sort_unstable_by_key(|v| v.vote_pubkey);
+    vote_accounts
+}
+
+fn sorted_pairs(map: &HashMap<Pubkey, Pubkey>) -> Vec<(Pubkey, Pubkey)> {
+    let mut pairs = map.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>();
+    pairs.sort_unstable();
+    pairs
+}
+
+impl From<(Epoch, &EpochStakes)> for EpochStakesSummary {
+    fn from((epoch, epoch_stakes): (Epoch, &EpochStakes)) -> Self {
+        Self {
+            epoch,
+            total_stake: epoch_stakes.total_stake,
+            vote_accounts: vote_accounts(&epoch_stakes.stakes),
+            authorized_voters: sorted_pairs(&epoch_stakes.epoch_authorized_voters),
+        }
+    }
+}
+
+impl From<(Epoch, &VersionedEpochStakes)> for EpochStakesSummary {
+    fn from((epoch, epoch_stakes): (Epoch, &VersionedEpochStakes)) -> Self {
+        let VersionedEpochStakes::Current {
+            stakes,
+            total_stake,
+            epoch_authorized_voters,
+            ..
+        } = epoch_stakes;
+        Self {
+            epoch,
+            total_stake: *total_stake,
+            vote_accounts: vote_accounts(stakes),
+            authorized_voters: sorted_pairs(epoch_authorized_voters),
+        }
+    }
+}
+
+impl SnapshotManifest {
+    /// Returns the stake delegations of the bank at the snapshot slot, ordered by stake account.
+    pub fn stake_delegations(&self) -> Vec<StakeDelegation> {
+        let mut delegations = self
+            .bank
+            .stakes
+            .stake_delegations
+            .iter()
+            .map(|(stake_pubkey, delegation)| StakeDelegation {
+                stake_pubkey: *stake_pubkey,
+                voter_pubkey: delegation.voter_pubkey,
+                stake: delegation.stake,
+                activation_epoch: delegation.activation_epoch,
+                deactivation_epoch: delegation.deactivation_epoch,
+            })
+            .collect::<Vec<_>>();
+        delegations.sort_unstable_by_key(|d| d.stake_pubkey);
+        delegations
+    }
+
+    /// Returns the vote accounts of the bank at the snapshot slot, ordered by vote account.
+    pub fn vote_accounts(&self) -> Vec<VoteAccountStake> {
+        vote_accounts(&self.bank.stakes)
+    }
+
+    /// Returns the stakes of each epoch the bank keeps around, ordered by epoch.
+    ///
+    /// Newer validators store epoch stakes in the extra manifest fields, which take precedence.
+    pub fn epoch_stakes(&self) -> Vec<EpochStakesSummary> {
+        let mut epochs = BTreeMap::new();
+        for (epoch, epoch_stakes) in &self.bank.epoch_stakes {
+            epochs.insert(*epoch, EpochStakesSummary::from((*epoch, epoch_stakes)));
+        }
+        for (epoch, epoch_stakes) in &self.extra_fields.versioned_epoch_stakes {
+            epochs.insert(*epoch, EpochStakesSummary::from((*epoch, epoch_stakes)));
+        }
+        epochs.into_values().collect()
+    }
+}
@@ -0,0 +1,106 @@
+use crate::solana::{deserialize_from, serialize_into};
+use crate::Result;
+use solana_sdk::clock::Slot;
+use solana_sdk::hash::Hash;
+use solana_sdk::transaction::TransactionError;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+/// Size of the transaction key slices kept by the status cache.
+pub const CACHED_KEY_SIZE: usize = 20;
+
+/// Serialized form of `solana_runtime::bank::BankSlotDelta`.
+type SerializedSlotDelta = (
+    Slot,
+    bool,
+    HashMap<Hash, (usize, Vec<([u8; CACHED_KEY_SIZE], TransactionResult)>)>,
+);
+
+pub type TransactionResult = std::result::Result<(), TransactionError>;
+
+/// Transaction statuses the status cache recorded for one slot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlotStatuses {
+    pub slot: Slot,
+    pub is_root: bool,
+    pub blockhashes: Vec<BlockhashStatuses>,
+}
+
+/// Statuses of transactions that used a recent blockhash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockhashStatuses {
+    pub blockhash: Hash,
+    /// Offset into the signature or message hash at which `key_slice` starts.
+    pub key_index: usize,
+    pub statuses: Vec<TransactionStatus>,
+}
+
+/// Status of a transaction, identified by a slice of its signature or message hash.
+///
+/// The validator only keeps `CACHED_KEY_SIZE` bytes of each key,
+/// so full signatures cannot be recovered from the status cache.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransactionStatus {
+    pub key_slice: [u8; CACHED_KEY_SIZE],
+    pub result: TransactionResult,
+}
+
+impl TransactionStatus {
+    /// Returns whether the key slice matches the given signature or message hash.
+    pub fn matches(&self, key_index: usize, key: &[u8]) -> bool {
+        key.get(key_index..key_index + CACHED_KEY_SIZE) == Some(&self.key_slice[..])
+    }
+}
+
+/// Decodes the `snapshots/status_cache` file, ordered by slot.
+pub fn read_status_cache<R: Read>(rd: R) -> Result<Vec<SlotStatuses>> {
+    let deltas: Vec<SerializedSlotDelta> = deserialize_from(rd)?;
+    let mut slots = deltas
+        .into_iter()
+        .map(|(slot, is_root, statuses)| {
+            let mut blockhashes = statuses
+                .into_iter()
+                .map(|(blockhash, (key_index, statuses))| BlockhashStatuses {
+                    blockhash,
+                    key_index,
+                    statuses: statuses
+                        .into_iter()
+                        .map(|(key_slice, result)| TransactionStatus { key_slice, result })
+                        .collect(),
+                })
+                .collect::<Vec<_>>();
+            blockhashes.sort_unstable_by_key(|b| b.blockhash);
+            SlotStatuses {
+                slot,
+                is_root,
+                blockhashes,
+            }
+        })
+        .collect::<Vec<_>>();
+    slots.sort_unstable_by_key(|s| s.slot);
+    Ok(slots)
+}
+
+/// Encodes statuses in the format of the `snapshots/status_cache` file.
+pub fn write_status_cache<W: Write>(wr: W, slots: &[SlotStatuses]) -> Result<()> {
+    let deltas = slots
+        .iter()
+        .map(|slot| {
+            let statuses = slot
+                .blockhashes
+                .iter()
+                .map(|b| {
+                    let statuses = b
+                        .statuses
+                        .iter()
+                        .map(|s| (s.key_slice, s.result.clone()))
+                        .collect();
+                    (b.blockhash, (b.key_index, statuses))
+                })
+                .collect();
+            (slot.slot, slot.is_root, statuses)
+        })
+        .collect::<Vec<SerializedSlotDelta>>();
+    serialize_into(wr, &deltas)?;
+    Ok(())
+}
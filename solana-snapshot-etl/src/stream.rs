@@ -0,0 +1,298 @@
+use crate::append_vec::{AccountMeta, StoredAccountMeta, StoredMeta, ALIGN_BOUNDARY_OFFSET};
+use crate::filter::{AccountFilter, FilteredAccount};
+use crate::{CorruptionReason, Result, SnapshotError};
+use solana_sdk::clock::Slot;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::system_instruction::MAX_PERMITTED_DATA_LENGTH;
+use std::io::{self, Read};
+
+const STORED_META_SIZE: usize = 48;
+const ACCOUNT_META_SIZE: usize = 56;
+const HASH_SIZE: usize = 32;
+const HEADER_SIZE: usize = STORED_META_SIZE + ACCOUNT_META_SIZE + HASH_SIZE;
+
+/// Reads the accounts of an AppendVec one at a time from a stream.
+///
+/// Unlike `AppendVec::new_from_reader`, only the current account is held in memory,
+/// so memory use is bounded by the largest account rather than the largest AppendVec.
+pub struct AppendVecReader<R: Read> {
+    rd: R,
+    slot: Slot,
+    id: u64,
+    current_len: usize,
+    offset: usize,
+    next_offset: usize,
+    meta: StoredMeta,
+    account_meta: AccountMeta,
+    hash: Hash,
+    data: Vec<u8>,
+}
+
+impl<R: Read> AppendVecReader<R> {
+    pub fn new(rd: R, current_len: usize, slot: Slot, id: u64) -> Self {
+        Self {
+            rd,
+            slot,
+            id,
+            current_len,
+            offset: 0,
+            next_offset: 0,
+            meta: StoredMeta {
+                write_version: 0,
+                data_len: 0,
+                pubkey: Pubkey::default(),
+            },
+            account_meta: AccountMeta::default(),
+            hash: Hash::default(),
+            data: Vec::new(),
+        }
+    }
+
+    pub fn slot(&self) -> Slot {
+        self.slot
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Reads the next account, or returns `None` once the AppendVec is exhausted.
+    ///
+    /// Follows the same bounds rules as `AppendVec::try_get_account`.
+    pub fn next_account(&mut self) -> Result<Option<StoredAccountMeta<'_>>> {
+        if !self.read_header()? {
+            return Ok(None);
+        }
+        self.read_data()?;
+        Ok(Some(self.account()))
+    }
+
+    /// Reads the next account that is not rejected by its pubkey alone.
+    ///
+    /// The data of accounts rejected by their header is skipped instead of copied.
+    pub fn next_filtered_account(
+        &mut self,
+        filter: &AccountFilter,
+    ) -> Result<Option<FilteredAccount<'_>>> {
+        loop {
+            if !self.read_header()? {
+                return Ok(None);
+            }
+            match filter.matches_header(&self.meta, &self.account_meta) {
+                Some(false) => {
+                    self.skip_data()?;
+                    if filter.matches_pubkey(&self.meta.pubkey) != Some(false) {
+                        return Ok(Some(FilteredAccount::Rejected(&self.meta)));
+                    }
+                }
+                Some(true) => {
+                    self.read_data()?;
+                    return Ok(Some(FilteredAccount::Matched(self.account())));
+                }
+                None => {
+                    self.read_data()?;
+                    let account = self.account();
+                    return Ok(Some(if filter.matches(&account) {
+                        FilteredAccount::Matched(account)
+                    } else {
+                        FilteredAccount::Rejected(account.meta)
+                    }));
+                }
+            }
+        }
+    }
+
+    /// Reads the header of the next account, or returns `false` once the AppendVec is exhausted.
+    fn read_header(&mut self) -> Result<bool> {
+        self.offset = self.next_offset;
+        if self.offset >= self.current_len {
+            return Ok(false);
+        }
+        let remaining = self.current_len - self.offset;
+        if remaining < STORED_META_SIZE + ACCOUNT_META_SIZE {
+            return Err(self.corrupt(CorruptionReason::MetaOutOfBounds));
+        }
+        if remaining < HEADER_SIZE {
+            return Err(self.corrupt(CorruptionReason::HashOutOfBounds));
+        }
+        let mut header = [0u8; HEADER_SIZE];
+        self.rd.read_exact(&mut header)?;
+        self.parse_header(&header);
+
+        let data_len = self.meta.data_len;
+        if data_len > MAX_PERMITTED_DATA_LENGTH || data_len as usize > remaining - HEADER_SIZE {
+            return Err(self.corrupt(CorruptionReason::DataLenOverflow));
+        }
+        let data_end = self.offset + HEADER_SIZE + data_len as usize;
+        self.next_offset = (data_end + (ALIGN_BOUNDARY_OFFSET - 1)) & !(ALIGN_BOUNDARY_OFFSET - 1);
+        Ok(true)
+    }
+
+    fn read_data(&mut self) -> Result<()> {
+        self.data.resize(self.meta.data_len as usize, 0);
+        self.rd.read_exact(&mut self.data)?;
+        self.skip_padding()
+    }
+
+    fn skip_data(&mut self) -> Result<()> {
+        self.data.clear();
+        self.skip(self.meta.data_len)?;
+        self.skip_padding()
+    }
+
+    fn skip_padding(&mut self) -> Result<()> {
+        let data_end = self.offset + HEADER_SIZE + self.meta.data_len as usize;
+        if self.next_offset <= self.current_len {
+            io::copy(
+                &mut (&mut self.rd).take((self.next_offset - data_end) as u64),
+                &mut io::sink(),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn skip(&mut self, len: u64) -> Result<()> {
+        let skipped = io::copy(&mut (&mut self.rd).take(len), &mut io::sink())?;
+        if skipped < len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(())
+    }
+
+    fn account(&self) -> StoredAccountMeta<'_> {
+        StoredAccountMeta {
+            meta: &self.meta,
+            account_meta: &self.account_meta,
+            data: &self.data,
+            offset: self.offset,
+            stored_size: self.next_offset - self.offset,
+            hash: &self.hash,
+        }
+    }
+
+    fn corrupt(&self, reason: CorruptionReason) -> SnapshotError {
+        SnapshotError::CorruptAppendVec {
+            slot: self.slot,
+            id: self.id,
+            offset: self.offset,
+            reason,
+        }
+    }
+
+    fn parse_header(&mut self, header: &[u8; HEADER_SIZE]) {
+        let u64_at = |pos: usize| u64::from_le_bytes(header[pos..pos + 8].try_into().unwrap());
+        let pubkey_at = |pos: usize| Pubkey::new(&header[pos..pos + 32]);
+
+        self.meta = StoredMeta {
+            write_version: u64_at(0),
+            data_len: u64_at(8),
+            pubkey: pubkey_at(16),
+        };
+        self.account_meta = AccountMeta {
+            lamports: u64_at(48),
+            rent_epoch: u64_at(56),
+            owner: pubkey_at(64),
+            executable: header[96] != 0,
+        };
+        self.hash = Hash::new(&header[104..136]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{append_vec_bytes, append_vec_from_bytes, pubkey, TestAccount};
+    use std::collections::HashSet;
+
+    /// Accounts with every field set, and data lengths that do and do not need padding.
+    fn accounts() -> Vec<TestAccount> {
+        [0usize, 1, 5, 8, 13, 16]
+            .into_iter()
+            .enumerate()
+            .map(|(i, data_len)| {
+                let data = (0..data_len).map(|b| (b + i) as u8).collect::<Vec<_>>();
+                let mut account =
+                    TestAccount::new(pubkey(i as u8 + 1), 100 + i as u64, 1 + i as u64, &data);
+                account.account_meta.rent_epoch = u64::MAX - i as u64;
+                account.account_meta.owner = pubkey(200 + i as u8);
+                account.account_meta.executable = i % 2 == 1;
+                account
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_accounts_written_in_append_vec_layout() {
+        let accounts = accounts();
+        let bytes = append_vec_bytes(9, &accounts);
+        let append_vec = append_vec_from_bytes(9, 2, &bytes);
+        let mut reader = AppendVecReader::new(&bytes[..], bytes.len(), 9, 2);
+
+        let mut offset = 0;
+        for account in &accounts {
+            let (expected, next) = append_vec.try_get_account(offset).unwrap().unwrap();
+            let read = reader.next_account().unwrap().unwrap();
+            assert_eq!(read, expected);
+            assert_eq!(read.meta, &account.meta);
+            assert_eq!(read.account_meta, &account.account_meta);
+            assert_eq!(read.data, &account.data[..]);
+            assert_eq!(*read.hash, account.hash(9));
+            assert_eq!(read.offset, offset);
+            assert_eq!(read.stored_size, next - offset);
+            offset = next;
+        }
+        assert_eq!(offset, bytes.len());
+        assert!(reader.next_account().unwrap().is_none());
+    }
+
+    #[test]
+    fn skips_data_of_rejected_accounts() {
+        let accounts = accounts();
+        let bytes = append_vec_bytes(9, &accounts);
+        let filter = AccountFilter::DataSize(6..=16).and(AccountFilter::Not(Box::new(
+            AccountFilter::Pubkey(HashSet::from([pubkey(6)])),
+        )));
+        let mut reader = AppendVecReader::new(&bytes[..], bytes.len(), 9, 2);
+        let mut seen = Vec::new();
+        while let Some(account) = reader.next_filtered_account(&filter).unwrap() {
+            seen.push(match account {
+                FilteredAccount::Matched(account) => {
+                    assert_eq!(account.data.len() as u64, account.meta.data_len);
+                    (account.meta.pubkey, true)
+                }
+                FilteredAccount::Rejected(meta) => (meta.pubkey, false),
+            });
+        }
+        // The last account is rejected by its pubkey alone, so it is not reported.
+        assert_eq!(
+            seen,
+            vec![
+                (pubkey(1), false),
+                (pubkey(2), false),
+                (pubkey(3), false),
+                (pubkey(4), true),
+                (pubkey(5), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn evaluates_data_filters_after_reading_data() {
+        let accounts = accounts();
+        let bytes = append_vec_bytes(9, &accounts);
+        // Data of account i starts with byte i.
+        let filter = AccountFilter::Memcmp {
+            offset: 0,
+            bytes: vec![4],
+        };
+        let mut reader = AppendVecReader::new(&bytes[..], bytes.len(), 9, 2);
+        let mut matched = Vec::new();
+        while let Some(account) = reader.next_filtered_account(&filter).unwrap() {
+            if let FilteredAccount::Matched(account) = account {
+                matched.push((account.meta.pubkey, account.data.to_vec()));
+            }
+        }
+        assert_eq!(matched, vec![(pubkey(5), accounts[4].data.clone())]);
+    }
+}
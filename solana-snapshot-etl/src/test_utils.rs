@@ -0,0 +1,96 @@
+use crate::append_vec::{AccountMeta, AppendVec, StoredAccountMeta, StoredMeta};
+use crate::manifest::SnapshotManifest;
+use crate::solana::{AccountsDbFields, DeserializableVersionedBank, ExtraFieldsToDeserialize};
+use crate::verify::hash_stored_account;
+use crate::writer::{stored_size, write_account};
+use solana_runtime::snapshot_utils::SnapshotVersion;
+use solana_sdk::clock::Slot;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+
+/// Account to be written into a test AppendVec.
+#[derive(Clone)]
+pub(crate) struct TestAccount {
+    pub meta: StoredMeta,
+    pub account_meta: AccountMeta,
+    pub data: Vec<u8>,
+}
+
+impl TestAccount {
+    pub fn new(pubkey: Pubkey, write_version: u64, lamports: u64, data: &[u8]) -> Self {
+        Self {
+            meta: StoredMeta {
+                write_version,
+                data_len: data.len() as u64,
+                pubkey,
+            },
+            account_meta: AccountMeta {
+                lamports,
+                rent_epoch: 3,
+                owner: Pubkey::new_from_array([7; 32]),
+                executable: false,
+            },
+            data: data.to_vec(),
+        }
+    }
+
+    pub fn access<'a>(&'a self, hash: &'a Hash) -> StoredAccountMeta<'a> {
+        StoredAccountMeta {
+            meta: &self.meta,
+            account_meta: &self.account_meta,
+            data: &self.data,
+            offset: 0,
+            stored_size: stored_size(self.data.len()),
+            hash,
+        }
+    }
+
+    /// Hash of the account stored in the given slot.
+    pub fn hash(&self, slot: Slot) -> Hash {
+        hash_stored_account(slot, &self.access(&Hash::default()))
+    }
+}
+
+/// Lays out the accounts like an AppendVec of the given slot, with their correct hashes.
+pub(crate) fn append_vec_bytes(slot: Slot, accounts: &[TestAccount]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for account in accounts {
+        write_account(
+            &mut bytes,
+            &account.meta,
+            &account.account_meta,
+            &account.hash(slot),
+            &account.data,
+        )
+        .unwrap();
+    }
+    bytes
+}
+
+pub(crate) fn append_vec_from_bytes(slot: Slot, id: u64, bytes: &[u8]) -> AppendVec {
+    AppendVec::new_from_reader(&mut &bytes[..], bytes.len(), slot, id).unwrap()
+}
+
+pub(crate) fn append_vec(slot: Slot, id: u64, accounts: &[TestAccount]) -> AppendVec {
+    append_vec_from_bytes(slot, id, &append_vec_bytes(slot, accounts))
+}
+
+/// Returns a pubkey that sorts by `n`.
+pub(crate) fn pubkey(n: u8) -> Pubkey {
+    Pubkey::new_from_array([n; 32])
+}
+
+/// Returns a minimal manifest of a bank at the given slot, without any accounts.
+pub(crate) fn manifest(slot: Slot) -> SnapshotManifest {
+    let mut bank = DeserializableVersionedBank::default();
+    bank.slot = slot;
+    bank.parent_slot = slot - 1;
+    bank.blockhash_queue
+        .register_hash(&Hash::new_unique(), 5000);
+    SnapshotManifest {
+        version: SnapshotVersion::V1_2_0,
+        bank,
+        accounts_db_fields: AccountsDbFields::default(),
+        extra_fields: ExtraFieldsToDeserialize::default(),
+    }
+}
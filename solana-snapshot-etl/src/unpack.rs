@@ -0,0 +1,173 @@
+use crate::filter::{AccountFilter, FilteredAccount};
+use crate::manifest::SnapshotManifest;
+use crate::stream::AppendVecReader;
+use crate::writer::{stored_size, write_account};
+use crate::{Result, SnapshotError, SNAPSHOTS_DIR, VERSION_FILE};
+use log::info;
+use solana_runtime::snapshot_utils::SNAPSHOT_STATUS_CACHE_FILENAME;
+use solana_sdk::clock::Slot;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Writes the contents of a snapshot archive into the directory layout
+/// read by `UnpackedSnapshotExtractor`.
+///
+/// Every AppendVec must hold at least the length the manifest lists for it,
+/// and its records are parsed while they are written, so corrupt AppendVecs are rejected.
+/// AppendVecs are rewritten to only hold the accounts matching the filter,
+/// and their storage entries in the manifest are updated to match.
+/// The filter must only select by pubkey: AppendVecs are written as they are read,
+/// so an older matching version could not be dropped once a newer version is rejected.
+/// The rest of the manifest is left untouched, so a filtered snapshot no longer matches its accounts hash.
+pub struct SnapshotUnpacker {
+    root: PathBuf,
+    filter: AccountFilter,
+    /// Length of each AppendVec seen so far, zero for those left without accounts.
+    lens: HashMap<(Slot, u64), usize>,
+    stats: UnpackStats,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct UnpackStats {
+    pub append_vecs: u64,
+    pub accounts: u64,
+    pub bytes: u64,
+}
+
+impl SnapshotUnpacker {
+    /// Creates the layout in `out_dir`, which must not exist or be empty.
+    pub fn create(out_dir: &Path, filter: AccountFilter) -> Result<Self> {
+        if !filter.is_pubkey_only() {
+            return Err(SnapshotError::UnsupportedUnpackFilter);
+        }
+        if out_dir.exists() && out_dir.read_dir()?.next().is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{:?} is not empty", out_dir),
+            )
+            .into());
+        }
+        std::fs::create_dir_all(out_dir.join(SNAPSHOTS_DIR))?;
+        std::fs::create_dir_all(out_dir.join("accounts"))?;
+        Ok(Self {
+            root: out_dir.to_path_buf(),
+            filter,
+            lens: HashMap::new(),
+            stats: UnpackStats::default(),
+        })
+    }
+
+    /// Writes the matching accounts of the AppendVec read from `rd`,
+    /// which holds `size` bytes, to `accounts/<slot>.<id>`.
+    pub fn write_append_vec<R: Read>(
+        &mut self,
+        manifest: &SnapshotManifest,
+        rd: R,
+        size: u64,
+        slot: Slot,
+        id: u64,
+    ) -> Result<()> {
+        let expected = manifest
+            .append_vec_entry(slot, id)
+            .ok_or(SnapshotError::UnexpectedAppendVec)?
+            .accounts_current_len;
+        if size < expected as u64 {
+            return Err(SnapshotError::AppendVecLengthMismatch {
+                slot,
+                id,
+                expected,
+                actual: size,
+            });
+        }
+
+        let path = self.root.join("accounts").join(format!("{}.{}", slot, id));
+        let mut wr = BufWriter::new(File::create(&path)?);
+        let mut reader = AppendVecReader::new(rd, expected, slot, id);
+        let mut len = 0;
+        while let Some(account) = reader.next_filtered_account(&self.filter)? {
+            if let FilteredAccount::Matched(account) = account {
+                write_account(
+                    &mut wr,
+                    account.meta,
+                    account.account_meta,
+                    account.hash,
+                    account.data,
+                )?;
+                len += stored_size(account.data.len());
+                self.stats.accounts += 1;
+            }
+        }
+        wr.into_inner().map_err(|e| e.into_error())?;
+        if len == 0 {
+            // Empty AppendVecs cannot be opened, so leave them out.
+            std::fs::remove_file(&path)?;
+        } else {
+            self.stats.append_vecs += 1;
+            self.stats.bytes += len as u64;
+        }
+        self.lens.insert((slot, id), len);
+        Ok(())
+    }
+
+    /// Writes the version file, the status cache and the manifest.
+    ///
+    /// The manifest is written last, so an interrupted unpack cannot be opened.
+    pub fn finish(
+        self,
+        manifest: &SnapshotManifest,
+        status_cache: Option<&[u8]>,
+    ) -> Result<UnpackStats> {
+        let missing = manifest
+            .accounts_db_fields
+            .0
+            .iter()
+            .flat_map(|(slot, entries)| entries.iter().map(move |entry| (*slot, entry)))
+            .filter(|(slot, entry)| {
+                entry.accounts_current_len > 0 && !self.lens.contains_key(&(*slot, entry.id as u64))
+            })
+            .count();
+        if missing > 0 {
+            return Err(SnapshotError::MissingAppendVecs(missing));
+        }
+        let status_cache = status_cache.ok_or(SnapshotError::NoStatusCache)?;
+
+        let mut manifest = manifest.clone();
+        let storages = &mut manifest.accounts_db_fields.0;
+        for (slot, entries) in storages.iter_mut() {
+            entries.retain_mut(|entry| {
+                match self.lens.get(&(*slot, entry.id as u64)) {
+                    Some(&len) if len > 0 => entry.accounts_current_len = len,
+                    _ => return false,
+                }
+                true
+            });
+        }
+        storages.retain(|_, entries| !entries.is_empty());
+
+        let slot = manifest.slot();
+        let snapshots_dir = self.root.join(SNAPSHOTS_DIR);
+        std::fs::write(
+            self.root.join(VERSION_FILE),
+            manifest.version.as_str().as_bytes(),
+        )?;
+        std::fs::write(
+            snapshots_dir.join(SNAPSHOT_STATUS_CACHE_FILENAME),
+            status_cache,
+        )?;
+        let manifest_dir = snapshots_dir.join(slot.to_string());
+        std::fs::create_dir_all(&manifest_dir)?;
+        let tmp_path = manifest_dir.join(format!("{}.tmp", slot));
+        let mut wr = BufWriter::new(File::create(&tmp_path)?);
+        manifest.write_to(&mut wr)?;
+        wr.flush()?;
+        drop(wr);
+        std::fs::rename(&tmp_path, manifest_dir.join(slot.to_string()))?;
+        info!(
+            "Unpacked {} accounts in {} AppendVecs ({} bytes) to {:?}",
+            self.stats.accounts, self.stats.append_vecs, self.stats.bytes, &self.root
+        );
+        Ok(self.stats)
+    }
+}
@@ -1,27 +1,49 @@
+use crate::dedup::LatestAccount;
+use crate::index::AccountIndex;
+use crate::manifest::parse_snapshot_version;
+use crate::status_cache::{read_status_cache, SlotStatuses};
 use crate::{
-    deserialize_from, parse_append_vec_name, AccountsDbFields, AppendVec, AppendVecIterator,
-    DeserializableVersionedBank, ReadProgressTracking, Result, SerializableAccountStorageEntry,
-    SnapshotError, SnapshotExtractor, SNAPSHOTS_DIR,
+    parse_append_vec_name, AppendVec, AppendVecIterator, ReadProgressTracking, Result,
+    SnapshotError, SnapshotExtractor, SnapshotManifest, SNAPSHOTS_DIR, VERSION_FILE,
 };
 use itertools::Itertools;
-use log::info;
-use solana_runtime::snapshot_utils::SNAPSHOT_STATUS_CACHE_FILENAME;
-use std::fs::OpenOptions;
+use log::{info, warn};
+use solana_runtime::snapshot_utils::{SnapshotVersion, SNAPSHOT_STATUS_CACHE_FILENAME};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::time::Instant;
 
 /// Extracts account data from snapshots that were unarchived to a file system.
 pub struct UnpackedSnapshotExtractor {
     root: PathBuf,
-    accounts_db_fields: AccountsDbFields<SerializableAccountStorageEntry>,
+    manifest: SnapshotManifest,
+    skip: HashSet<(u64, u64)>,
 }
 
 impl SnapshotExtractor for UnpackedSnapshotExtractor {
     fn iter(&mut self) -> AppendVecIterator<'_> {
         Box::new(self.unboxed_iter())
     }
+
+    fn manifest(&self) -> &SnapshotManifest {
+        &self.manifest
+    }
+
+    fn status_cache(&self) -> Result<Option<Vec<SlotStatuses>>> {
+        let path = self
+            .root
+            .join(SNAPSHOTS_DIR)
+            .join(SNAPSHOT_STATUS_CACHE_FILENAME);
+        let file = BufReader::new(File::open(path)?);
+        read_status_cache(file).map(Some)
+    }
+
+    fn skip_append_vecs(&mut self, append_vecs: HashSet<(u64, u64)>) {
+        self.skip = append_vecs;
+    }
 }
 
 impl UnpackedSnapshotExtractor {
@@ -32,6 +54,17 @@ impl UnpackedSnapshotExtractor {
             return Err(SnapshotError::NoStatusCache);
         }
 
+        let version_path = path.join(VERSION_FILE);
+        let version = if version_path.is_file() {
+            parse_snapshot_version(&std::fs::read_to_string(&version_path)?)?
+        } else {
+            warn!(
+                "No snapshot version file, assuming {}",
+                SnapshotVersion::default()
+            );
+            SnapshotVersion::default()
+        };
+
         let snapshot_files = snapshots_dir.read_dir()?;
 
         let snapshot_file_path = snapshot_files
@@ -49,30 +82,12 @@ impl UnpackedSnapshotExtractor {
             Box::new(snapshot_file),
             snapshot_file_len,
         );
-        let mut snapshot_file = BufReader::new(snapshot_file);
-
-        let pre_unpack = Instant::now();
-        let versioned_bank: DeserializableVersionedBank = deserialize_from(&mut snapshot_file)?;
-        drop(versioned_bank);
-        let versioned_bank_post_time = Instant::now();
-
-        let accounts_db_fields: AccountsDbFields<SerializableAccountStorageEntry> =
-            deserialize_from(&mut snapshot_file)?;
-        let accounts_db_fields_post_time = Instant::now();
-        drop(snapshot_file);
-
-        info!(
-            "Read bank fields in {:?}",
-            versioned_bank_post_time - pre_unpack
-        );
-        info!(
-            "Read accounts DB fields in {:?}",
-            accounts_db_fields_post_time - versioned_bank_post_time
-        );
+        let manifest = SnapshotManifest::read_from(BufReader::new(snapshot_file), version)?;
 
         Ok(UnpackedSnapshotExtractor {
             root: path.to_path_buf(),
-            accounts_db_fields,
+            manifest,
+            skip: HashSet::new(),
         })
     }
 
@@ -91,27 +106,76 @@ impl UnpackedSnapshotExtractor {
                 let name = f.file_name();
                 parse_append_vec_name(&f.file_name()).map(move |parsed| (parsed, name))
             })
+            .filter(|(parsed, _)| !self.skip.contains(parsed))
             .map(move |((slot, version), name)| {
                 self.open_append_vec(slot, version, &accounts_dir.join(name))
             }))
     }
 
+    /// Looks up the latest version of an account, or `None` if it does not exist.
+    pub fn get_account(
+        &self,
+        index: &AccountIndex,
+        pubkey: &Pubkey,
+    ) -> Result<Option<LatestAccount>> {
+        Ok(self
+            .get_multiple_accounts(index, &[*pubkey])?
+            .pop()
+            .flatten())
+    }
+
+    /// Looks up the latest version of each account, in the order of `pubkeys`.
+    ///
+    /// Each AppendVec holding one of the accounts is only opened once.
+    pub fn get_multiple_accounts(
+        &self,
+        index: &AccountIndex,
+        pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<LatestAccount>>> {
+        if index.slot() != self.manifest.slot() {
+            return Err(SnapshotError::AccountIndexMismatch {
+                index_slot: index.slot(),
+                snapshot_slot: self.manifest.slot(),
+            });
+        }
+        let mut entries = pubkeys
+            .iter()
+            .enumerate()
+            .filter_map(|(pos, pubkey)| index.get(pubkey).map(|entry| (pos, entry)))
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|(_, entry)| (entry.slot, entry.id, entry.offset));
+
+        let accounts_dir = self.root.join("accounts");
+        let mut accounts = vec![None; pubkeys.len()];
+        for (_, group) in &entries.iter().group_by(|(_, entry)| (entry.slot, entry.id)) {
+            let mut append_vec = None;
+            for (pos, entry) in group {
+                if append_vec.is_none() {
+                    let path = accounts_dir.join(format!("{}.{}", entry.slot, entry.id));
+                    append_vec = Some(self.open_append_vec(entry.slot, entry.id, &path)?);
+                }
+                let append_vec = append_vec.as_ref().unwrap();
+                let account = match append_vec.try_get_account(entry.offset)? {
+                    Some((account, _)) if account.meta.pubkey == entry.pubkey => account,
+                    _ => return Err(SnapshotError::CorruptAccountIndex("stale entry")),
+                };
+                accounts[*pos] = Some(LatestAccount::new(entry.slot, entry.id, &account));
+            }
+        }
+        Ok(accounts)
+    }
+
     fn open_append_vec(&self, slot: u64, id: u64, path: &Path) -> Result<AppendVec> {
-        let known_vecs = self
-            .accounts_db_fields
-            .0
-            .get(&slot)
-            .map(|v| &v[..])
-            .unwrap_or(&[]);
-        let known_vec = known_vecs.iter().find(|entry| entry.id == (id as usize));
-        let known_vec = match known_vec {
-            None => return Err(SnapshotError::UnexpectedAppendVec),
-            Some(v) => v,
-        };
+        let known_vec = self
+            .manifest
+            .append_vec_entry(slot, id)
+            .ok_or(SnapshotError::UnexpectedAppendVec)?;
 
         Ok(AppendVec::new_from_file(
             path,
             known_vec.accounts_current_len,
+            slot,
+            id,
         )?)
     }
 }
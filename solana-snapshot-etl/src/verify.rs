@@ -0,0 +1,288 @@
+use crate::append_vec::{AppendVec, StoredAccountMeta};
+use crate::dedup::LatestAccount;
+use crate::{Result, SnapshotError};
+use solana_runtime::accounts_db::AccountsDb;
+use solana_runtime::accounts_hash::MERKLE_FANOUT;
+use solana_sdk::clock::Slot;
+use solana_sdk::hash::{Hash, Hasher};
+use solana_sdk::pubkey::Pubkey;
+use std::fmt::{Display, Formatter};
+
+/// A stored account whose hash does not match its contents.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccountHashMismatch {
+    pub slot: Slot,
+    pub id: u64,
+    pub offset: usize,
+    pub pubkey: Pubkey,
+    pub stored_hash: Hash,
+    pub computed_hash: Hash,
+}
+
+impl Display for AccountHashMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Account {} in AppendVec {}.{} at offset {} has hash {}, expected {}",
+            self.pubkey, self.slot, self.id, self.offset, self.stored_hash, self.computed_hash
+        )
+    }
+}
+
+/// Recomputes the hash of an account stored in the given slot, like the validator does.
+pub fn hash_stored_account(slot: Slot, account: &StoredAccountMeta) -> Hash {
+    AccountsDb::hash_account(slot, account, &account.meta.pubkey)
+}
+
+/// Checks the stored hash of an account in the AppendVec with the given slot and id.
+pub fn verify_account_hash(
+    slot: Slot,
+    id: u64,
+    account: &StoredAccountMeta,
+) -> Option<AccountHashMismatch> {
+    let computed_hash = hash_stored_account(slot, account);
+    if computed_hash == *account.hash {
+        return None;
+    }
+    Some(AccountHashMismatch {
+        slot,
+        id,
+        offset: account.offset,
+        pubkey: account.meta.pubkey,
+        stored_hash: *account.hash,
+        computed_hash,
+    })
+}
+
+/// Checks the stored hash of every account in an AppendVec.
+pub fn verify_append_vec_hashes(append_vec: &AppendVec) -> Result<Vec<AccountHashMismatch>> {
+    let mut mismatches = Vec::new();
+    let mut offset = 0usize;
+    while let Some((account, next_offset)) = append_vec.try_get_account(offset)? {
+        mismatches.extend(verify_account_hash(
+            append_vec.slot(),
+            append_vec.id(),
+            &account,
+        ));
+        offset = next_offset;
+    }
+    Ok(mismatches)
+}
+
+/// Incrementally computes the accounts hash, like `AccountsHash::accumulate_account_hashes`.
+///
+/// Account hashes must be added in ascending pubkey order.
+/// Only one pending chunk per level of the Merkle tree is kept in memory.
+#[derive(Default)]
+pub struct AccountsHasher {
+    levels: Vec<MerkleLevel>,
+}
+
+#[derive(Default)]
+struct MerkleLevel {
+    pending: Vec<Hash>,
+    total: u64,
+}
+
+impl AccountsHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, hash: Hash) {
+        self.push(0, hash);
+    }
+
+    fn push(&mut self, level: usize, hash: Hash) {
+        if self.levels.len() == level {
+            self.levels.push(MerkleLevel::default());
+        }
+        if self.levels[level].pending.len() == MERKLE_FANOUT {
+            let parent = hash_chunk(&self.levels[level].pending);
+            self.levels[level].pending.clear();
+            self.push(level + 1, parent);
+        }
+        let merkle_level = &mut self.levels[level];
+        merkle_level.pending.push(hash);
+        merkle_level.total += 1;
+    }
+
+    pub fn finish(mut self) -> Hash {
+        if self.levels.is_empty() {
+            return Hasher::default().result();
+        }
+        let mut level = 0;
+        loop {
+            let parent = hash_chunk(&self.levels[level].pending);
+            if self.levels[level].total <= MERKLE_FANOUT as u64 {
+                return parent;
+            }
+            self.levels[level].pending.clear();
+            self.push(level + 1, parent);
+            level += 1;
+        }
+    }
+}
+
+fn hash_chunk(hashes: &[Hash]) -> Hash {
+    let mut hasher = Hasher::default();
+    for hash in hashes {
+        hasher.hash(hash.as_ref());
+    }
+    hasher.result()
+}
+
+/// Computes the accounts hash over the latest version of each account.
+///
+/// Accounts must be ordered by pubkey, as returned by `AccountDeduplicator::finish`.
+/// Zero-lamport accounts are excluded, like the validator does.
+/// The hash of each account is recomputed from its contents,
+/// and an account whose stored hash differs fails with `AccountHashMismatch`.
+pub fn compute_accounts_hash<I>(accounts: I) -> Result<Hash>
+where
+    I: Iterator<Item = Result<LatestAccount>>,
+{
+    let mut hasher = AccountsHasher::new();
+    for account in accounts {
+        let account = account?;
+        if account.account_meta.lamports == 0 {
+            continue;
+        }
+        let computed_hash = hash_stored_account(account.slot, &account.access());
+        if computed_hash != account.hash {
+            return Err(SnapshotError::AccountHashMismatch(Box::new(
+                AccountHashMismatch {
+                    slot: account.slot,
+                    id: account.id,
+                    offset: account.offset,
+                    pubkey: account.meta.pubkey,
+                    stored_hash: account.hash,
+                    computed_hash,
+                },
+            )));
+        }
+        hasher.add(computed_hash);
+    }
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{
+        append_vec, append_vec_bytes, append_vec_from_bytes, pubkey, TestAccount,
+    };
+    use crate::writer::stored_size;
+    use solana_runtime::accounts_hash::AccountsHash;
+    use solana_sdk::hash::hashv;
+
+    #[test]
+    fn hashes_stored_accounts_like_the_runtime() {
+        let mut executable = TestAccount::new(pubkey(2), 9, 1_000_000, &[0xaa; 37]);
+        executable.account_meta.executable = true;
+        let accounts = [
+            TestAccount::new(pubkey(1), 1, 42, &[]),
+            executable,
+            TestAccount::new(pubkey(3), 7, u64::MAX, &[1, 2, 3]),
+            TestAccount::new(pubkey(4), 2, 0, &[5; 8]),
+        ];
+        for account in &accounts {
+            for slot in [0, 1, 123_456_789] {
+                let hash = Hash::default();
+                let stored = account.access(&hash);
+                assert_eq!(
+                    hash_stored_account(slot, &stored),
+                    AccountsDb::hash_account(slot, &stored.clone_account(), &account.meta.pubkey)
+                );
+            }
+        }
+        // The slot is part of the hash, but not for deleted accounts.
+        assert_ne!(accounts[0].hash(1), accounts[0].hash(2));
+        assert_eq!(accounts[3].hash(1), Hash::default());
+    }
+
+    #[test]
+    fn accounts_hasher_matches_merkle_root() {
+        for len in [0u64, 1, 16, 17, 257] {
+            let hashes = (0..len)
+                .map(|i| hashv(&[&i.to_le_bytes()]))
+                .collect::<Vec<_>>();
+            let mut hasher = AccountsHasher::new();
+            for hash in &hashes {
+                hasher.add(*hash);
+            }
+            let expected = AccountsHash::compute_merkle_root(
+                hashes
+                    .iter()
+                    .map(|hash| (Pubkey::default(), *hash))
+                    .collect(),
+                MERKLE_FANOUT,
+            );
+            assert_eq!(hasher.finish(), expected, "{} leaves", len);
+        }
+    }
+
+    #[test]
+    fn reports_flipped_byte() {
+        let accounts = [
+            TestAccount::new(pubkey(1), 1, 10, &[1; 5]),
+            TestAccount::new(pubkey(2), 2, 20, &[2; 16]),
+            TestAccount::new(pubkey(3), 3, 30, &[3; 3]),
+        ];
+        assert!(verify_append_vec_hashes(&append_vec(5, 2, &accounts))
+            .unwrap()
+            .is_empty());
+
+        let mut bytes = append_vec_bytes(5, &accounts);
+        let offset = stored_size(5);
+        bytes[offset + 136 + 4] ^= 1;
+        let mismatches = verify_append_vec_hashes(&append_vec_from_bytes(5, 2, &bytes)).unwrap();
+        assert_eq!(
+            mismatches,
+            vec![AccountHashMismatch {
+                slot: 5,
+                id: 2,
+                offset,
+                pubkey: pubkey(2),
+                stored_hash: accounts[1].hash(5),
+                computed_hash: mismatches[0].computed_hash,
+            }]
+        );
+        assert_ne!(mismatches[0].computed_hash, mismatches[0].stored_hash);
+    }
+
+    fn latest(slot: Slot, account: &TestAccount) -> LatestAccount {
+        LatestAccount::new(slot, 0, &account.access(&account.hash(slot)))
+    }
+
+    #[test]
+    fn accounts_hash_excludes_zero_lamport_accounts() {
+        let accounts = [
+            latest(4, &TestAccount::new(pubkey(1), 1, 10, &[1])),
+            latest(6, &TestAccount::new(pubkey(2), 2, 0, &[])),
+            latest(5, &TestAccount::new(pubkey(3), 3, 30, &[3; 9])),
+        ];
+        let mut hasher = AccountsHasher::new();
+        hasher.add(accounts[0].hash);
+        hasher.add(accounts[2].hash);
+        let computed = compute_accounts_hash(accounts.into_iter().map(Ok)).unwrap();
+        assert_eq!(computed, hasher.finish());
+    }
+
+    #[test]
+    fn accounts_hash_fails_on_stored_hash_mismatch() {
+        let mut tampered = latest(5, &TestAccount::new(pubkey(2), 2, 20, &[2; 4]));
+        tampered.data[0] ^= 1;
+        let accounts = [
+            latest(4, &TestAccount::new(pubkey(1), 1, 10, &[1])),
+            tampered,
+        ];
+        match compute_accounts_hash(accounts.into_iter().map(Ok)) {
+            Err(SnapshotError::AccountHashMismatch(mismatch)) => {
+                assert_eq!((mismatch.slot, mismatch.pubkey), (5, pubkey(2)));
+                assert_ne!(mismatch.computed_hash, mismatch.stored_hash);
+            }
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+    }
+}
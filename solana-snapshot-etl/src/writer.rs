@@ -0,0 +1,370 @@
+use crate::append_vec::{
+    AccountMeta, StoredMeta, ALIGN_BOUNDARY_OFFSET, MAXIMUM_APPEND_VEC_FILE_SIZE,
+};
+use crate::dedup::LatestAccount;
+use crate::filter::AccountFilter;
+use crate::manifest::SnapshotManifest;
+use crate::solana::SerializableAccountStorageEntry;
+use crate::status_cache::{write_status_cache, SlotStatuses};
+use crate::verify::{hash_stored_account, AccountsHasher};
+use crate::{Result, SnapshotError, SNAPSHOTS_DIR, VERSION_FILE};
+use log::info;
+use solana_runtime::inline_spl_token;
+use solana_runtime::snapshot_utils::SNAPSHOT_STATUS_CACHE_FILENAME;
+use solana_sdk::hash::{extend_and_hash, hashv, Hash};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tar::{Builder, EntryType, Header};
+use tempfile::NamedTempFile;
+
+/// Writes accounts into a new full snapshot archive that validators can boot from.
+///
+/// Accounts are laid out in fresh AppendVecs at the snapshot slot, with their hashes recomputed for it.
+/// A new AppendVec is started whenever the current one would exceed `MAXIMUM_APPEND_VEC_FILE_SIZE`.
+/// The manifest is rewritten to match: storage entries, capitalization,
+/// accounts data length, the accounts hash and the bank hash are recomputed,
+/// and stakes are pruned to the stake and vote accounts that were written.
+pub struct SnapshotWriter {
+    manifest: SnapshotManifest,
+    spill_dir: PathBuf,
+    /// Completed AppendVecs and their lengths, in id order.
+    append_vecs: Vec<(NamedTempFile, usize)>,
+    append_vec: BufWriter<NamedTempFile>,
+    len: usize,
+    max_append_vec_len: u64,
+    accounts_count: u64,
+    hasher: AccountsHasher,
+    capitalization: u64,
+    accounts_data_len: u64,
+    last_pubkey: Option<Pubkey>,
+    stake_accounts: HashSet<Pubkey>,
+}
+
+impl SnapshotWriter {
+    pub fn new(manifest: SnapshotManifest, spill_dir: &Path) -> Result<Self> {
+        Ok(Self {
+            manifest,
+            spill_dir: spill_dir.to_path_buf(),
+            append_vecs: Vec::new(),
+            append_vec: new_append_vec(spill_dir)?,
+            len: 0,
+            max_append_vec_len: MAXIMUM_APPEND_VEC_FILE_SIZE,
+            accounts_count: 0,
+            hasher: AccountsHasher::new(),
+            capitalization: 0,
+            accounts_data_len: 0,
+            last_pubkey: None,
+            stake_accounts: HashSet::new(),
+        })
+    }
+
+    /// Matches the accounts a bank cannot be restored without:
+    /// builtin programs, sysvars, feature activations and the native SOL mint.
+    pub fn required_accounts() -> AccountFilter {
+        AccountFilter::Or(vec![
+            AccountFilter::Owner(HashSet::from([
+                solana_sdk::native_loader::id(),
+                solana_sdk::sysvar::id(),
+                solana_sdk::feature::id(),
+            ])),
+            AccountFilter::Pubkey(HashSet::from([inline_spl_token::native_mint::id()])),
+        ])
+    }
+
+    /// Appends the latest version of an account.
+    ///
+    /// Accounts must be pushed in ascending pubkey order, as returned by `AccountDeduplicator::finish`.
+    /// Zero-lamport accounts are deleted, so they are skipped.
+    pub fn push(&mut self, account: &LatestAccount) -> Result<()> {
+        let pubkey = account.meta.pubkey;
+        if self.last_pubkey.is_some_and(|last| last >= pubkey) {
+            return Err(SnapshotError::UnsortedAccounts);
+        }
+        self.last_pubkey = Some(pubkey);
+        if account.account_meta.lamports == 0 {
+            return Ok(());
+        }
+
+        let stored_size = stored_size(account.data.len());
+        if stored_size as u64 > self.max_append_vec_len {
+            return Err(SnapshotError::AppendVecTooLarge);
+        }
+        if (self.len + stored_size) as u64 > self.max_append_vec_len {
+            self.start_append_vec()?;
+        }
+        // The account hash covers the slot, so it changes along with the account's slot.
+        let hash = hash_stored_account(self.manifest.slot(), &account.access());
+        write_account(
+            &mut self.append_vec,
+            &account.meta,
+            &account.account_meta,
+            &hash,
+            &account.data,
+        )?;
+        self.len += stored_size;
+
+        self.accounts_count += 1;
+        self.hasher.add(hash);
+        self.capitalization += account.account_meta.lamports;
+        self.accounts_data_len += account.data.len() as u64;
+        let owner = &account.account_meta.owner;
+        if solana_sdk::stake::program::check_id(owner) || solana_sdk::vote::program::check_id(owner)
+        {
+            self.stake_accounts.insert(pubkey);
+        }
+        Ok(())
+    }
+
+    /// Completes the current AppendVec and starts a new one.
+    fn start_append_vec(&mut self) -> Result<()> {
+        let append_vec = std::mem::replace(&mut self.append_vec, new_append_vec(&self.spill_dir)?);
+        let append_vec = append_vec.into_inner().map_err(|e| e.into_error())?;
+        self.append_vecs.push((append_vec, self.len));
+        self.len = 0;
+        Ok(())
+    }
+
+    pub fn push_all<I>(&mut self, accounts: I) -> Result<()>
+    where
+        I: Iterator<Item = Result<LatestAccount>>,
+    {
+        for account in accounts {
+            self.push(&account?)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `snapshot-<slot>-<accounts hash>.tar.zst` into `out_dir` and returns its path.
+    pub fn finish(mut self, out_dir: &Path, status_cache: &[SlotStatuses]) -> Result<PathBuf> {
+        if self.len > 0 {
+            self.start_append_vec()?;
+        }
+        let mut append_vecs = self.append_vecs;
+
+        let mut manifest = self.manifest;
+        let slot = manifest.slot();
+        let accounts_hash = self.hasher.finish();
+        let stake_accounts = self.stake_accounts;
+        manifest.accounts_db_fields.0 = HashMap::new();
+        if !append_vecs.is_empty() {
+            let entries = append_vecs
+                .iter()
+                .enumerate()
+                .map(|(id, (_, len))| SerializableAccountStorageEntry {
+                    id,
+                    accounts_current_len: *len,
+                })
+                .collect();
+            manifest.accounts_db_fields.0.insert(slot, entries);
+        }
+        // All accounts are stored in the snapshot slot, so its accounts delta hash,
+        // which the validator checks against the bank hash, covers every account.
+        manifest.accounts_db_fields.3.hash = accounts_hash;
+        manifest.accounts_db_fields.3.snapshot_hash = accounts_hash;
+        manifest.bank.hash = bank_hash(&manifest, accounts_hash);
+        manifest.bank.capitalization = self.capitalization;
+        manifest.bank.accounts_data_len = self.accounts_data_len;
+        let stakes = &mut manifest.bank.stakes;
+        stakes
+            .vote_accounts
+            .retain(|pubkey, _| stake_accounts.contains(pubkey));
+        stakes
+            .stake_delegations
+            .retain(|pubkey, _| stake_accounts.contains(pubkey));
+        manifest.extra_fields.incremental_snapshot_persistence = None;
+        manifest.extra_fields.epoch_accounts_hash = None;
+
+        let mut manifest_buf = Vec::new();
+        manifest.write_to(&mut manifest_buf)?;
+        let mut status_cache_buf = Vec::new();
+        write_status_cache(&mut status_cache_buf, status_cache)?;
+
+        let name = format!("snapshot-{}-{}.tar.zst", slot, accounts_hash);
+        let path = out_dir.join(&name);
+        let tmp_path = out_dir.join(format!("tmp-{}", name));
+        let encoder = zstd::Encoder::new(File::create(&tmp_path)?, 0)?;
+        let mut builder = Builder::new(encoder);
+        let version = manifest.version.as_str().as_bytes();
+        append_file(&mut builder, VERSION_FILE, version.len(), version)?;
+        append_dir(&mut builder, SNAPSHOTS_DIR)?;
+        append_dir(&mut builder, &format!("{}/{}", SNAPSHOTS_DIR, slot))?;
+        append_file(
+            &mut builder,
+            &format!("{}/{}/{}", SNAPSHOTS_DIR, slot, slot),
+            manifest_buf.len(),
+            &manifest_buf[..],
+        )?;
+        append_file(
+            &mut builder,
+            &format!("{}/{}", SNAPSHOTS_DIR, SNAPSHOT_STATUS_CACHE_FILENAME),
+            status_cache_buf.len(),
+            &status_cache_buf[..],
+        )?;
+        append_dir(&mut builder, "accounts")?;
+        for (id, (append_vec, len)) in append_vecs.iter_mut().enumerate() {
+            append_vec.seek(SeekFrom::Start(0))?;
+            append_file(
+                &mut builder,
+                &format!("accounts/{}.{}", slot, id),
+                *len,
+                append_vec.as_file_mut(),
+            )?;
+        }
+        builder.into_inner()?.finish()?.sync_all()?;
+        std::fs::rename(&tmp_path, &path)?;
+        info!(
+            "Wrote {} accounts ({} lamports) to {:?}",
+            self.accounts_count, self.capitalization, &path
+        );
+        Ok(path)
+    }
+}
+
+fn new_append_vec(spill_dir: &Path) -> Result<BufWriter<NamedTempFile>> {
+    let append_vec = tempfile::Builder::new()
+        .prefix("snapshot-writer")
+        .tempfile_in(spill_dir)?;
+    Ok(BufWriter::new(append_vec))
+}
+
+/// Returns the size of an account record in an AppendVec, including padding.
+pub(crate) fn stored_size(data_len: usize) -> usize {
+    let padding = data_len.wrapping_neg() & (ALIGN_BOUNDARY_OFFSET - 1);
+    136 + data_len + padding
+}
+
+/// Writes an account record in the AppendVec layout, padded to the alignment of the next record.
+pub(crate) fn write_account<W: Write>(
+    wr: &mut W,
+    meta: &StoredMeta,
+    account_meta: &AccountMeta,
+    hash: &Hash,
+    data: &[u8],
+) -> std::io::Result<()> {
+    let padding = data.len().wrapping_neg() & (ALIGN_BOUNDARY_OFFSET - 1);
+    wr.write_all(&meta.write_version.to_le_bytes())?;
+    wr.write_all(&(data.len() as u64).to_le_bytes())?;
+    wr.write_all(meta.pubkey.as_ref())?;
+    wr.write_all(&account_meta.lamports.to_le_bytes())?;
+    wr.write_all(&account_meta.rent_epoch.to_le_bytes())?;
+    wr.write_all(account_meta.owner.as_ref())?;
+    wr.write_all(&[account_meta.executable as u8, 0, 0, 0, 0, 0, 0, 0])?;
+    wr.write_all(hash.as_ref())?;
+    wr.write_all(data)?;
+    wr.write_all(&[0u8; ALIGN_BOUNDARY_OFFSET][..padding])?;
+    Ok(())
+}
+
+/// Computes the bank hash like `Bank::hash_internal_state`.
+fn bank_hash(manifest: &SnapshotManifest, accounts_delta_hash: Hash) -> Hash {
+    let bank = &manifest.bank;
+    let hash = hashv(&[
+        bank.parent_hash.as_ref(),
+        accounts_delta_hash.as_ref(),
+        &bank.signature_count.to_le_bytes(),
+        bank.blockhash_queue.last_hash().as_ref(),
+    ]);
+    match bank.hard_forks.get_hash_data(bank.slot, bank.parent_slot) {
+        Some(buf) => extend_and_hash(&hash, &buf),
+        None => hash,
+    }
+}
+
+fn append_dir<W: Write>(builder: &mut Builder<W>, path: &str) -> Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_mode(0o755);
+    header.set_size(0);
+    builder.append_data(&mut header, path, std::io::empty())?;
+    Ok(())
+}
+
+fn append_file<W: Write, R: std::io::Read>(
+    builder: &mut Builder<W>,
+    path: &str,
+    len: usize,
+    data: R,
+) -> Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(len as u64);
+    builder.append_data(&mut header, path, data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archived::ArchiveSnapshotExtractor;
+    use crate::dedup::dedup_accounts;
+    use crate::test_utils::{manifest, pubkey, TestAccount};
+    use crate::verify::compute_accounts_hash;
+    use crate::{CorruptionPolicy, SnapshotExtractor};
+
+    #[test]
+    fn round_trips_through_archive_extractor() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts: Vec<_> = (1..=5)
+            .map(|n| {
+                TestAccount::new(
+                    pubkey(n),
+                    n as u64,
+                    10 * n as u64,
+                    &vec![n; 40 * n as usize],
+                )
+            })
+            .chain(std::iter::once(TestAccount::new(pubkey(6), 6, 0, &[])))
+            .collect();
+
+        let mut writer = SnapshotWriter::new(manifest(10), dir.path()).unwrap();
+        // Small enough that the accounts span several AppendVecs.
+        writer.max_append_vec_len = 400;
+        for account in &accounts {
+            // The writer rehashes accounts for the snapshot slot.
+            let hash = Hash::default();
+            writer
+                .push(&LatestAccount::new(3, 0, &account.access(&hash)))
+                .unwrap();
+        }
+        let path = writer.finish(dir.path(), &[]).unwrap();
+
+        let mut extractor = ArchiveSnapshotExtractor::open(&path).unwrap();
+        let manifest = extractor.manifest().clone();
+        assert_eq!(manifest.slot(), 10);
+        let entries = &manifest.accounts_db_fields.0[&10];
+        assert!(entries.len() > 1);
+        assert_eq!(
+            entries.iter().map(|e| e.id).collect::<Vec<_>>(),
+            (0..entries.len()).collect::<Vec<_>>()
+        );
+        assert_eq!(manifest.bank.capitalization, 150);
+
+        let latest: Vec<_> = dedup_accounts(
+            &mut extractor,
+            CorruptionPolicy::Strict,
+            &AccountFilter::All,
+            dir.path(),
+            usize::MAX,
+        )
+        .unwrap()
+        .collect::<Result<_>>()
+        .unwrap();
+        assert_eq!(latest.len(), 5);
+        for (account, expected) in latest.iter().zip(&accounts) {
+            assert_eq!(account.slot, 10);
+            assert_eq!(account.meta.pubkey, expected.meta.pubkey);
+            assert_eq!(account.account_meta, expected.account_meta);
+            assert_eq!(account.data, expected.data);
+        }
+
+        let accounts_hash = compute_accounts_hash(latest.into_iter().map(Ok)).unwrap();
+        assert_eq!(accounts_hash, *manifest.accounts_hash());
+        assert_eq!(
+            path.file_name().unwrap().to_str().unwrap(),
+            format!("snapshot-10-{}.tar.zst", accounts_hash)
+        );
+    }
+}